@@ -4,6 +4,8 @@
 mod polkadot_presale_contract {
     use token_factory::TokenFactoryRef;
     use token_lock::TokenLockRef;
+    use token_contract::{TokenContractRef, PSP22, PSP22Burnable, PSP22Metadata};
+    use reentrancy_guard::ReentrancyGuard;
     use ink::storage::{
         Mapping as StorageHashMap
     };
@@ -11,6 +13,10 @@ mod polkadot_presale_contract {
     use ink::prelude::{
         vec::Vec,
     };
+    use ink::codegen::TraitCallBuilder;
+    use ink::env::CallFlags;
+    use ink::ToAccountId;
+    use ink::env::hash::Blake2x256;
 
     #[ink::scale_derive(Encode, Decode, TypeInfo)]
     #[cfg_attr(
@@ -26,16 +32,427 @@ mod polkadot_presale_contract {
         start_time: Timestamp,
         end_time: Timestamp,
         creator: AccountId,
-        contributors: Vec<AccountId>,
+        // Lock contract the project was created with, kept even if the
+        // contract-wide lock address is rotated later.
+        token_lock: AccountId,
+        // Number of accounts holding a contribution.
+        contributors_count: u32,
         is_finished: bool,
         is_successful: bool,
+        // Share of each referred contribution credited to the referrer.
+        referral_percentage: u8,
+        // Set once `extend_presale` has been used.
+        is_extended: bool,
+        withdrawal_penalty: WithdrawalPenalty,
+        unsold_token_policy: UnsoldTokenPolicy,
     }
+
+    #[derive(Debug, Default, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout)
+    )]
+    pub struct ReferralStats {
+        referred_contributions: u32,
+        referred_amount: Balance,
+        reward: Balance,
+        claimed: bool,
+    }
+
+    #[derive(Debug, Default, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout)
+    )]
+    pub struct Contribution {
+        token_amount: Balance,
+        paid_amount: Balance,
+        // The first referrer of a buyer is credited for all their later purchases.
+        referrer: Option<AccountId>,
+        referred_purchases: u32,
+        referred_amount: Balance,
+        referral_reward: Balance,
+    }
+
+    /// What happens to presale tokens that are still unsold when the presale is finished.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout)
+    )]
+    pub enum UnsoldTokenPolicy {
+        /// Burned through the token's `PSP22Burnable` interface.
+        Burn,
+        ReturnToCreator,
+        /// Sent to the liquidity pool set by the contract owner.
+        LiquidityPool,
+    }
+
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout)
+    )]
+    pub enum PenaltyRecipient {
+        #[default]
+        Creator,
+        /// The platform treasury set by the contract owner.
+        Treasury,
+    }
+
+    /// Share of the paid amount kept when a contributor withdraws from a running presale.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout)
+    )]
+    pub struct WithdrawalPenalty {
+        pub percentage: u8,
+        pub recipient: PenaltyRecipient,
+    }
+
+    #[derive(Debug, Default, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout)
+    )]
+    pub struct Stake {
+        amount: Balance,
+        // Reset on every top-up, so stake duration can't be gamed by
+        // adding most of the stake right before a sale.
+        staked_at: Timestamp,
+    }
+
+    /// An allocation tier of a project, reached by staking at least
+    /// `min_stake` platform tokens for at least `min_staking_duration`.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout)
+    )]
+    pub struct Tier {
+        pub min_stake: Balance,
+        pub min_staking_duration: Timestamp,
+        /// Maximum amount of presale tokens a member of the tier can buy.
+        pub max_allocation: Balance,
+        /// How long before `start_time` members of the tier can buy.
+        pub early_access: Timestamp,
+    }
+
+    /// Registration phase and draw of a project whose buyers are picked by lottery.
+    #[derive(Debug, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout)
+    )]
+    pub struct Lottery {
+        registration_deposit: Balance,
+        /// Maximum amount of presale tokens a winner can buy.
+        ticket_size: Balance,
+        winners: u32,
+        registration_end: Timestamp,
+        /// End of the phase in which registrants reveal their entropy.
+        reveal_end: Timestamp,
+        /// `blake2x256` of the seed the creator reveals in `draw_lottery`.
+        seed_commitment: Hash,
+        /// Entropy revealed by the registrants, mixed into the draw.
+        entropy: Hash,
+        is_drawn: bool,
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout)
+    )]
+    pub struct Registration {
+        deposit: Balance,
+        /// `blake2x256` of the entropy the registrant reveals in `reveal_lottery_entropy`.
+        entropy_commitment: Hash,
+        entropy_revealed: bool,
+        is_winner: bool,
+        withdrawn: bool,
+    }
+
+    /// Public key of the account signing attestations for a project.
+    ///
+    /// Only ECDSA is supported, as sr25519 verification relies on an unstable
+    /// host function that production chains don't expose.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout)
+    )]
+    pub enum VerifierKey {
+        /// Compressed secp256k1 public key.
+        Ecdsa([u8; 33]),
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout)
+    )]
+    pub struct AttestationRequirement {
+        pub verifier: VerifierKey,
+        /// Jurisdiction flags every attestation must carry.
+        pub required_flags: u32,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    pub enum AttestationSignature {
+        /// Recoverable signature, the last byte being the recovery id.
+        Ecdsa([u8; 65]),
+    }
+
+    /// An off-chain KYC attestation of a buyer, signed by the project's verifier
+    /// over `attestation_hash(project_id, buyer, expires_at, flags)`.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    pub struct Attestation {
+        pub expires_at: Timestamp,
+        pub flags: u32,
+        pub signature: AttestationSignature,
+    }
+
+    /// A backend-approved allocation, redeemable once per `nonce` by the buyer
+    /// it was signed for.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    pub struct Voucher {
+        /// Amount of presale tokens to buy.
+        pub amount: Balance,
+        /// Total native amount paid for `amount` tokens.
+        pub price: Balance,
+        pub deadline: Timestamp,
+        pub nonce: u64,
+    }
+
+    /// Descriptive information of a project, maintained by its creator.
+    #[derive(Debug, Default, Clone, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout)
+    )]
+    pub struct ProjectMetadata {
+        pub description: String,
+        pub website: String,
+        pub socials: Vec<String>,
+        pub whitepaper_hash: Option<Hash>,
+        pub ipfs_cid: String,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout)
+    )]
+    pub enum AllocationCategory {
+        Team,
+        Advisors,
+        Marketing,
+        Ecosystem,
+    }
+
+    /// A tokenomics allocation set aside at presale creation and vested
+    /// to `beneficiary` through the lock contract.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout)
+    )]
+    pub struct Allocation {
+        pub category: AllocationCategory,
+        pub beneficiary: AccountId,
+        pub amount: Balance,
+        /// Time after creation before anything vests.
+        pub cliff: Timestamp,
+        /// Time after the cliff over which the allocation vests.
+        pub vesting_duration: Timestamp,
+    }
+
+    /// Flags set by the contract owner to curate the project list.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout)
+    )]
+    pub struct Moderation {
+        pub verified: bool,
+        pub hidden: bool,
+    }
+
+    /// Read-only view of a project returned by `get_project`.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    pub struct ProjectView {
+        pub project_id: u32,
+        pub token: AccountId,
+        pub total_presale_token_amount: Balance,
+        pub presaled_amount: Balance,
+        pub intended_raise_amount: Balance,
+        pub raised_amount: Balance,
+        pub start_time: Timestamp,
+        pub end_time: Timestamp,
+        pub creator: AccountId,
+        pub token_lock: AccountId,
+        pub contributors_count: u32,
+        pub is_finished: bool,
+        pub is_successful: bool,
+        pub referral_percentage: u8,
+        pub is_extended: bool,
+        pub withdrawal_penalty: WithdrawalPenalty,
+        pub unsold_token_policy: UnsoldTokenPolicy,
+        pub allocations: Vec<Allocation>,
+        pub metadata: ProjectMetadata,
+        pub moderation: Moderation,
+    }
+
+    #[ink(event)]
+    pub struct ProjectMetadataUpdated {
+        #[ink(topic)]
+        project_id: u32,
+        metadata: ProjectMetadata,
+    }
+
+    #[ink(event)]
+    pub struct ProjectModerated {
+        #[ink(topic)]
+        project_id: u32,
+        moderation: Moderation,
+    }
+
+    #[ink(event)]
+    pub struct PresaleExtended {
+        #[ink(topic)]
+        project_id: u32,
+        previous_end_time: Timestamp,
+        end_time: Timestamp,
+    }
+
+    #[ink(event)]
+    pub struct PresaleClosedEarly {
+        #[ink(topic)]
+        project_id: u32,
+        end_time: Timestamp,
+        raised_amount: Balance,
+    }
+
+    #[ink(event)]
+    pub struct ContributionWithdrawn {
+        #[ink(topic)]
+        project_id: u32,
+        #[ink(topic)]
+        contributor: AccountId,
+        token_amount: Balance,
+        refund: Balance,
+        penalty: Balance,
+    }
+
+    /// Max-buy limit growing linearly from `initial_max_buy` to `final_max_buy`
+    /// during the first `duration` after `start_time`, and lifted afterwards.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout)
+    )]
+    pub struct MaxBuyRamp {
+        pub duration: Timestamp,
+        pub initial_max_buy: Balance,
+        pub final_max_buy: Balance,
+    }
+
+    /// Optional limits applied to `join_project_presale` against bots.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout)
+    )]
+    pub struct AntiSniping {
+        /// Maximum amount of tokens sold within a single block.
+        pub max_tokens_per_block: Option<Balance>,
+        /// Minimum time between two purchases of the same account.
+        pub cooldown: Option<Timestamp>,
+        pub ramp: Option<MaxBuyRamp>,
+    }
+
+    #[ink(event)]
+    pub struct UnsoldTokensHandled {
+        #[ink(topic)]
+        project_id: u32,
+        policy: UnsoldTokenPolicy,
+        amount: Balance,
+        /// Receiver of the tokens, `None` if they were burned.
+        recipient: Option<AccountId>,
+    }
+
+    /// Project id, buyer and nonce of a redeemed voucher.
+    type VoucherNonce = (u32, AccountId, u64);
+
+    /// Upper bound for `Project::referral_percentage`.
+    const MAX_REFERRAL_PERCENTAGE: u8 = 20;
+    /// Upper bound for the number of tiers of a project.
+    const MAX_TIERS: usize = 10;
+    /// Upper bound for the number of lottery registrations of a project.
+    const MAX_LOTTERY_ENTRANTS: usize = 250;
+    /// Upper bound for `WithdrawalPenalty::percentage`.
+    const MAX_WITHDRAWAL_PENALTY: u8 = 50;
+    /// Upper bound for how far `extend_presale` can push `end_time` (7 days).
+    const MAX_PRESALE_EXTENSION: Timestamp = 7 * 24 * 60 * 60 * 1000;
+    /// Upper bound for the length of each metadata text field.
+    const MAX_METADATA_FIELD_LENGTH: usize = 512;
+    /// Upper bound for the number of social links in the metadata.
+    const MAX_SOCIALS: usize = 8;
+    /// Upper bound for the number of tokenomics allocations of a project.
+    const MAX_ALLOCATIONS: usize = 8;
+
     #[ink(storage)]
     pub struct PolkadotPresaleContract {
         projects: StorageHashMap<u32, Project>,
         last_project_id: u32,
         token_factory: TokenFactoryRef,
         token_lock: TokenLockRef,
+        guard: ReentrancyGuard,
+        owner: AccountId,
+        referrals: StorageHashMap<(u32, AccountId), ReferralStats>,
+        contributions: StorageHashMap<(u32, AccountId), Contribution>,
+        staking_token: Option<AccountId>,
+        stakes: StorageHashMap<AccountId, Stake>,
+        total_staked: Balance,
+        project_tiers: StorageHashMap<u32, Vec<Tier>>,
+        lotteries: StorageHashMap<u32, Lottery>,
+        lottery_entrants: StorageHashMap<u32, Vec<AccountId>>,
+        registrations: StorageHashMap<(u32, AccountId), Registration>,
+        attestation_requirements: StorageHashMap<u32, AttestationRequirement>,
+        voucher_signers: StorageHashMap<u32, [u8; 33]>,
+        used_voucher_nonces: StorageHashMap<VoucherNonce, ()>,
+        project_metadata: StorageHashMap<u32, ProjectMetadata>,
+        moderation: StorageHashMap<u32, Moderation>,
+        treasury: Option<AccountId>,
+        anti_sniping: StorageHashMap<u32, AntiSniping>,
+        // Block number and amount of tokens sold in it, per project.
+        block_sales: StorageHashMap<u32, (BlockNumber, Balance)>,
+        last_purchases: StorageHashMap<(u32, AccountId), Timestamp>,
+        liquidity_pool: Option<AccountId>,
+        allocations: StorageHashMap<u32, Vec<Allocation>>,
     }
 
     impl PolkadotPresaleContract {
@@ -43,8 +460,79 @@ mod polkadot_presale_contract {
         pub fn new(token_factory_address: AccountId, token_lock_address: AccountId) -> Self {
             let token_factory: TokenFactoryRef = ink::env::call::FromAccountId::from_account_id(token_factory_address);
             let token_lock: TokenLockRef = ink::env::call::FromAccountId::from_account_id(token_lock_address);
-            
-            Self { token_factory, token_lock, last_project_id: 0, projects: StorageHashMap::new()}
+
+            Self {
+                token_factory,
+                token_lock,
+                last_project_id: 0,
+                projects: StorageHashMap::new(),
+                guard: ReentrancyGuard::default(),
+                owner: Self::env().caller(),
+                referrals: StorageHashMap::new(),
+                contributions: StorageHashMap::new(),
+                staking_token: None,
+                stakes: StorageHashMap::new(),
+                total_staked: 0,
+                project_tiers: StorageHashMap::new(),
+                lotteries: StorageHashMap::new(),
+                lottery_entrants: StorageHashMap::new(),
+                registrations: StorageHashMap::new(),
+                attestation_requirements: StorageHashMap::new(),
+                voucher_signers: StorageHashMap::new(),
+                used_voucher_nonces: StorageHashMap::new(),
+                project_metadata: StorageHashMap::new(),
+                moderation: StorageHashMap::new(),
+                treasury: None,
+                anti_sniping: StorageHashMap::new(),
+                block_sales: StorageHashMap::new(),
+                last_purchases: StorageHashMap::new(),
+                liquidity_pool: None,
+                allocations: StorageHashMap::new(),
+            }
+        }
+
+        #[ink(message)]
+        pub fn get_token_factory(&self) -> AccountId {
+            self.token_factory.to_account_id()
+        }
+
+        #[ink(message)]
+        pub fn get_token_lock(&self) -> AccountId {
+            self.token_lock.to_account_id()
+        }
+
+        /// Points new presales at another token factory.
+        ///
+        /// The new address is probed with `get_fee` first, so a wrong or
+        /// incompatible account is rejected before anything is switched.
+        #[ink(message)]
+        pub fn set_token_factory(&mut self, token_factory_address: AccountId) {
+            assert!(self.env().caller() == self.owner, "Only the owner can change the token factory");
+
+            let token_factory: TokenFactoryRef = ink::env::call::FromAccountId::from_account_id(token_factory_address);
+            assert!(
+                matches!(token_factory.call().get_fee().try_invoke(), Ok(Ok(_))),
+                "Address does not implement the token factory interface"
+            );
+
+            self.token_factory = token_factory;
+        }
+
+        /// Points new presales at another lock contract.
+        ///
+        /// The new address is probed with the lock-specific `get_lock` first. Existing
+        /// projects keep the lock contract they were created with.
+        #[ink(message)]
+        pub fn set_token_lock(&mut self, token_lock_address: AccountId) {
+            assert!(self.env().caller() == self.owner, "Only the owner can change the token lock");
+
+            let token_lock: TokenLockRef = ink::env::call::FromAccountId::from_account_id(token_lock_address);
+            assert!(
+                matches!(token_lock.call().get_lock(0).try_invoke(), Ok(Ok(_))),
+                "Address does not implement the token lock interface"
+            );
+
+            self.token_lock = token_lock;
         }
 
         #[ink(message)]
@@ -61,93 +549,2226 @@ mod polkadot_presale_contract {
             intended_raise_amount: Balance,
             start_time: Timestamp,
             end_time: Timestamp,
+            unsold_token_policy: UnsoldTokenPolicy,
+            allocations: Vec<Allocation>,
+        ) {
+            self.guard.enter().expect("Reentrant call detected");
+
+            // The factory mints the whole supply to its caller, i.e. this contract,
+            // so the presale allocation is escrowed here from the start.
+            let token_address = self.token_factory.create_token(max_supply, name, symbol, decimals, logo_uri);
+            let total_presale_token_amount = max_supply
+                .checked_sub(lock_amount)
+                .and_then(|amount| amount.checked_sub(Self::allocated_amount(&allocations)))
+                .expect("Lock amount and allocations exceed max supply");
+
+            let mut token: TokenContractRef = ink::env::call::FromAccountId::from_account_id(token_address);
+            self.register_project(&mut token, 0, total_presale_token_amount, lock_amount, lock_duartion, intended_raise_amount, start_time, end_time, unsold_token_policy, allocations);
+
+            self.guard.exit();
+        }
+
+        /// Creates a presale for an already deployed PSP22 token.
+        ///
+        /// The caller must have approved this contract for `presale_amount + lock_amount`
+        /// plus the allocated amounts, which are pulled in with
+        /// `transfer_from`. `decimals` has to match the token's metadata.
+        #[ink(message)]
+        #[allow(clippy::too_many_arguments)]
+        pub fn create_presale_for_token(
+            &mut self,
+            token_address: AccountId,
+            presale_amount: Balance,
+            decimals: u8,
+            lock_amount: Balance,
+            lock_duartion: Timestamp,
+            intended_raise_amount: Balance,
+            start_time: Timestamp,
+            end_time: Timestamp,
+            unsold_token_policy: UnsoldTokenPolicy,
+            allocations: Vec<Allocation>,
         ) {
+            self.guard.enter().expect("Reentrant call detected");
+
+            let mut token: TokenContractRef = ink::env::call::FromAccountId::from_account_id(token_address);
+            assert!(token.token_decimals() == decimals, "Token decimals mismatch");
+
+            // Other projects may already hold this token here, so only the increase counts.
+            let escrow_before = token.balance_of(self.env().account_id());
+            let deposit = presale_amount
+                .checked_add(lock_amount)
+                .and_then(|amount| amount.checked_add(Self::allocated_amount(&allocations)))
+                .expect("Invalid Operation");
+            token
+                .transfer_from(self.env().caller(), self.env().account_id(), deposit, Vec::new())
+                .expect("Token transfer failed");
+
+            self.register_project(&mut token, escrow_before, presale_amount, lock_amount, lock_duartion, intended_raise_amount, start_time, end_time, unsold_token_policy, allocations);
+
+            self.guard.exit();
+        }
+
+        // Stores a new project for tokens already held by this contract, moves
+        // `lock_amount` and the tokenomics allocations into the lock contract and
+        // checks that the presale allocation remains escrowed here.
+        #[allow(clippy::too_many_arguments)]
+        fn register_project(
+            &mut self,
+            token: &mut TokenContractRef,
+            escrow_before: Balance,
+            total_presale_token_amount: Balance,
+            lock_amount: Balance,
+            lock_duartion: Timestamp,
+            intended_raise_amount: Balance,
+            start_time: Timestamp,
+            end_time: Timestamp,
+            unsold_token_policy: UnsoldTokenPolicy,
+            allocations: Vec<Allocation>,
+        ) -> u32 {
             let project_id = self.last_project_id.checked_add(1).expect("Overflow detected in project_id calculation");
             self.last_project_id = project_id;
 
-            let token_address = self.token_factory.create_token(max_supply, name, symbol, decimals, logo_uri);
-            let _ = self.token_lock.create_lock(token_address, self.env().caller(), lock_amount, lock_duartion);
+            let token_address = token.to_account_id();
+            match unsold_token_policy {
+                UnsoldTokenPolicy::Burn => {
+                    // Burning nothing is a no-op for any `PSP22Burnable` token.
+                    let mut burnable: ink::contract_ref!(PSP22Burnable) = token_address.into();
+                    assert!(
+                        matches!(burnable.call_mut().burn(0).try_invoke(), Ok(Ok(Ok(())))),
+                        "Token is not burnable"
+                    );
+                }
+                UnsoldTokenPolicy::LiquidityPool => {
+                    assert!(self.liquidity_pool.is_some(), "Liquidity pool not set");
+                }
+                UnsoldTokenPolicy::ReturnToCreator => {}
+            }
 
             let project = Project {
                 token: token_address,
-                total_presale_token_amount: max_supply.checked_sub(lock_amount).expect("error"),
+                total_presale_token_amount,
                 presaled_amount: 0,
                 intended_raise_amount,
                 raised_amount: 0,
                 start_time,
                 end_time,
                 creator: self.env().caller(),
-                contributors: Vec::new(),
+                token_lock: self.token_lock.to_account_id(),
+                contributors_count: 0,
                 is_finished: false,
                 is_successful: false,
+                referral_percentage: 0,
+                is_extended: false,
+                withdrawal_penalty: WithdrawalPenalty::default(),
+                unsold_token_policy,
             };
 
             self.projects.insert(project_id, &project);
+            self.allocations.insert(project_id, &allocations);
+
+            // The project is stored before handing control to the lock contract.
+            let approval = lock_amount.checked_add(Self::allocated_amount(&allocations)).expect("Invalid Operation");
+            token.approve(self.token_lock.to_account_id(), approval).expect("Token approval failed");
+            if lock_amount > 0 {
+                self.token_lock
+                    .create_lock(token_address, self.env().caller(), lock_amount, lock_duartion)
+                    .expect("Token lock failed");
+            }
+            for allocation in &allocations {
+                let vesting_start = self.time_now().checked_add(allocation.cliff).expect("Invalid Operation");
+                self.token_lock
+                    .create_vesting_lock(token_address, allocation.beneficiary, allocation.amount, vesting_start, 0, allocation.vesting_duration, None)
+                    .expect("Token lock failed");
+            }
+
+            // Reverts the whole creation unless the presale allocation is held by this contract.
+            let escrowed = token.balance_of(self.env().account_id()).saturating_sub(escrow_before);
+            assert!(escrowed >= total_presale_token_amount, "Presale tokens not escrowed");
+
+            project_id
         }
 
+        #[ink(message)]
+        pub fn get_project(&self, project_id: u32) -> Option<ProjectView> {
+            let project = self.projects.get(project_id)?;
+
+            Some(ProjectView {
+                project_id,
+                token: project.token,
+                total_presale_token_amount: project.total_presale_token_amount,
+                presaled_amount: project.presaled_amount,
+                intended_raise_amount: project.intended_raise_amount,
+                raised_amount: project.raised_amount,
+                start_time: project.start_time,
+                end_time: project.end_time,
+                creator: project.creator,
+                token_lock: project.token_lock,
+                contributors_count: project.contributors_count,
+                is_finished: project.is_finished,
+                is_successful: project.is_successful,
+                referral_percentage: project.referral_percentage,
+                is_extended: project.is_extended,
+                withdrawal_penalty: project.withdrawal_penalty,
+                unsold_token_policy: project.unsold_token_policy,
+                allocations: self.allocations.get(project_id).unwrap_or_default(),
+                metadata: self.project_metadata.get(project_id).unwrap_or_default(),
+                moderation: self.moderation.get(project_id).unwrap_or_default(),
+            })
+        }
 
+        /// Replaces the metadata of a project.
+        ///
+        /// Only the project creator can change it, and only before the presale starts.
         #[ink(message)]
-        pub fn join_project_presale(
+        pub fn update_project_metadata(
             &mut self,
             project_id: u32,
-            buy_token_amount: Balance,
+            metadata: ProjectMetadata,
         ) {
-            let caller = self.env().caller();
-            let mut project = self.projects.get(project_id).expect("Project not found");
+            let project = self.projects.get(project_id).expect("Project not found");
 
-            assert!(project.start_time <= self.time_now(), "Presale not started");
-            assert!(project.end_time > self.time_now(), "Presale ended");
-
-            let cost = self.calculate_price(project.presaled_amount, buy_token_amount);
-            assert!(cost < self.env().transferred_value(), "Insufficient payment");
-            assert!(project.presaled_amount.checked_add(buy_token_amount) < Some(project.total_presale_token_amount), "Insufficient amount");
+            assert!(self.env().caller() == project.creator, "Only the creator can update metadata");
+            assert!(project.start_time > self.time_now(), "Presale already started");
+            assert!(metadata.socials.len() <= MAX_SOCIALS, "Too many social links");
+            assert!(
+                [&metadata.description, &metadata.website, &metadata.ipfs_cid]
+                    .into_iter()
+                    .chain(metadata.socials.iter())
+                    .all(|field| field.len() <= MAX_METADATA_FIELD_LENGTH),
+                "Metadata field too long"
+            );
 
-            project.presaled_amount = project.presaled_amount.checked_add(buy_token_amount).expect("Invalid Operation");
-            project.raised_amount = project.raised_amount.checked_add(cost).expect("Invalid Operation");
-            project.contributors.push(caller);
+            self.project_metadata.insert(project_id, &metadata);
+            self.env().emit_event(ProjectMetadataUpdated { project_id, metadata });
         }
 
         #[ink(message)]
-        pub fn finish_presale(
+        pub fn set_project_moderation(
             &mut self,
             project_id: u32,
+            moderation: Moderation,
         ) {
-            let mut project = self.projects.get(project_id).expect("Project not found");
+            assert!(self.env().caller() == self.owner, "Only the owner can moderate projects");
+            assert!(self.projects.contains(project_id), "Project not found");
 
-            assert!(project.end_time > self.time_now(), "Presale not finished");
-            project.is_finished = true;
-            project.is_successful = project.intended_raise_amount / 3 <= project.raised_amount;
+            self.moderation.insert(project_id, &moderation);
+            self.env().emit_event(ProjectModerated { project_id, moderation });
+        }
+
+        /// Sets the platform token users stake to reach allocation tiers.
+        ///
+        /// Can't be changed while anything is staked.
+        #[ink(message)]
+        pub fn set_staking_token(&mut self, staking_token_address: AccountId) {
+            assert!(self.env().caller() == self.owner, "Only the owner can change the staking token");
+            assert!(self.total_staked == 0, "Staking token still has stakes");
 
-            // Add more logic here
+            self.staking_token = Some(staking_token_address);
         }
 
         #[ink(message)]
-        pub fn calculate_price(
-            &self,
-            presaled_amount: Balance,
-            buy_token_amount: Balance,
-        ) -> Balance {
-            let k: Balance = 1;
-            let c: Balance = 0;
+        pub fn get_staking_token(&self) -> Option<AccountId> {
+            self.staking_token
+        }
 
-            let current_price = k.checked_mul(presaled_amount).unwrap_or(0).checked_add(c).unwrap_or(0);
-            let next_price = k
-                .checked_mul(presaled_amount.checked_add(buy_token_amount).unwrap_or(0))
-                .unwrap_or(0)
-                .checked_add(c)
-                .unwrap_or(0);
+        /// Stakes `amount` platform tokens, pulled from the caller with `transfer_from`.
+        ///
+        /// Restarts the staking duration of the caller.
+        #[ink(message)]
+        pub fn stake(&mut self, amount: Balance) {
+            self.guard.enter().expect("Reentrant call detected");
 
-            current_price
-                .checked_add(next_price)
-                .unwrap_or(0)
-                .checked_mul(buy_token_amount)
-                .unwrap_or(0)
-                .checked_div(2)
-                .unwrap_or(0)
+            let caller = self.env().caller();
+            let staking_token = self.staking_token.expect("Staking is not enabled");
+            assert!(amount > 0, "Nothing to stake");
+
+            let mut stake = self.stakes.get(caller).unwrap_or_default();
+            stake.amount = stake.amount.checked_add(amount).expect("Invalid Operation");
+            stake.staked_at = self.time_now();
+            self.stakes.insert(caller, &stake);
+            self.total_staked = self.total_staked.checked_add(amount).expect("Invalid Operation");
+
+            // Staking tokens may call back into the contract, which the guard rejects.
+            let mut token: TokenContractRef = ink::env::call::FromAccountId::from_account_id(staking_token);
+            token
+                .call_mut()
+                .transfer_from(caller, self.env().account_id(), amount, Vec::new())
+                .call_flags(CallFlags::ALLOW_REENTRY)
+                .invoke()
+                .expect("Token transfer failed");
+
+            self.guard.exit();
         }
 
-        pub fn time_now(&self) -> Timestamp {
-            self.env().block_timestamp()
+        #[ink(message)]
+        pub fn unstake(&mut self, amount: Balance) {
+            self.guard.enter().expect("Reentrant call detected");
+
+            let caller = self.env().caller();
+            let staking_token = self.staking_token.expect("Staking is not enabled");
+
+            let mut stake = self.stakes.get(caller).expect("Nothing staked");
+            stake.amount = stake.amount.checked_sub(amount).expect("Insufficient stake");
+            if stake.amount == 0 {
+                self.stakes.remove(caller);
+            } else {
+                self.stakes.insert(caller, &stake);
+            }
+            self.total_staked = self.total_staked.checked_sub(amount).expect("Invalid Operation");
+
+            let mut token: TokenContractRef = ink::env::call::FromAccountId::from_account_id(staking_token);
+            token
+                .call_mut()
+                .transfer(caller, amount, Vec::new())
+                .call_flags(CallFlags::ALLOW_REENTRY)
+                .invoke()
+                .expect("Token transfer failed");
+
+            self.guard.exit();
+        }
+
+        #[ink(message)]
+        pub fn get_stake(&self, account: AccountId) -> Stake {
+            self.stakes.get(account).unwrap_or_default()
+        }
+
+        /// Restricts the presale to stakers, ordered from the lowest tier to the highest.
+        ///
+        /// Only the project creator can change it, and only before the presale starts.
+        /// An empty list opens the presale to everyone again.
+        #[ink(message)]
+        pub fn set_project_tiers(
+            &mut self,
+            project_id: u32,
+            tiers: Vec<Tier>,
+        ) {
+            let project = self.projects.get(project_id).expect("Project not found");
+
+            assert!(self.env().caller() == project.creator, "Only the creator can configure tiers");
+            assert!(project.start_time > self.time_now(), "Presale already started");
+            assert!(tiers.len() <= MAX_TIERS, "Too many tiers");
+            assert!(
+                tiers.windows(2).all(|pair| pair[0].min_stake <= pair[1].min_stake),
+                "Tiers must be ordered by stake"
+            );
+
+            if tiers.is_empty() {
+                self.project_tiers.remove(project_id);
+            } else {
+                self.project_tiers.insert(project_id, &tiers);
+            }
+        }
+
+        #[ink(message)]
+        pub fn get_project_tiers(&self, project_id: u32) -> Vec<Tier> {
+            self.project_tiers.get(project_id).unwrap_or_default()
+        }
+
+        /// Returns the index of the highest tier of the project `account` currently qualifies for.
+        #[ink(message)]
+        pub fn get_tier(&self, project_id: u32, account: AccountId) -> Option<u32> {
+            self.tier_of(project_id, account).map(|(index, _)| index)
+        }
+
+        fn tier_of(&self, project_id: u32, account: AccountId) -> Option<(u32, Tier)> {
+            let stake = self.stakes.get(account)?;
+            let staked_for = self.time_now().saturating_sub(stake.staked_at);
+
+            self.project_tiers
+                .get(project_id)
+                .unwrap_or_default()
+                .into_iter()
+                .zip(0u32..)
+                .filter(|(tier, _)| stake.amount >= tier.min_stake && staked_for >= tier.min_staking_duration)
+                .last()
+                .map(|(tier, index)| (index, tier))
+        }
+
+        /// Turns the presale into a lottery.
+        ///
+        /// Until `registration_end` anyone but the creator can register by paying
+        /// `registration_deposit` and committing to some entropy, which they reveal
+        /// until `reveal_end`. The creator then reveals the seed behind
+        /// `seed_commitment` to draw `winners` of the registrants that revealed,
+        /// who are the only ones able to buy, each up to `ticket_size` tokens.
+        ///
+        /// A lottery that isn't drawn before `start_time` is void, and the presale
+        /// is then open to everyone.
+        #[ink(message)]
+        #[allow(clippy::too_many_arguments)]
+        pub fn set_project_lottery(
+            &mut self,
+            project_id: u32,
+            registration_deposit: Balance,
+            ticket_size: Balance,
+            winners: u32,
+            registration_end: Timestamp,
+            reveal_end: Timestamp,
+            seed_commitment: Hash,
+        ) {
+            let project = self.projects.get(project_id).expect("Project not found");
+
+            assert!(self.env().caller() == project.creator, "Only the creator can configure the lottery");
+            assert!(!self.lotteries.contains(project_id), "Lottery already configured");
+            assert!(winners > 0, "Lottery needs winners");
+            assert!(
+                self.time_now() < registration_end && registration_end < reveal_end && reveal_end <= project.start_time,
+                "Registration and reveal must end before the presale starts"
+            );
+
+            let lottery = Lottery {
+                registration_deposit,
+                ticket_size,
+                winners,
+                registration_end,
+                reveal_end,
+                seed_commitment,
+                entropy: Hash::default(),
+                is_drawn: false,
+            };
+            self.lotteries.insert(project_id, &lottery);
+        }
+
+        #[ink(message)]
+        pub fn get_lottery(&self, project_id: u32) -> Option<Lottery> {
+            self.lotteries.get(project_id)
+        }
+
+        /// Registers the caller for a lottery with the `blake2x256` hash of the
+        /// entropy they reveal before the draw.
+        #[ink(message, payable)]
+        pub fn register_for_lottery(&mut self, project_id: u32, entropy_commitment: Hash) {
+            let caller = self.env().caller();
+            let project = self.projects.get(project_id).expect("Project not found");
+            let lottery = self.lotteries.get(project_id).expect("Lottery not found");
+
+            assert!(caller != project.creator, "Creator cannot register");
+            assert!(self.time_now() < lottery.registration_end, "Registration ended");
+            assert!(self.env().transferred_value() == lottery.registration_deposit, "Wrong registration deposit");
+            assert!(!self.registrations.contains((project_id, caller)), "Already registered");
+
+            let mut entrants = self.lottery_entrants.get(project_id).unwrap_or_default();
+            assert!(entrants.len() < MAX_LOTTERY_ENTRANTS, "Lottery is full");
+            entrants.push(caller);
+            self.lottery_entrants.insert(project_id, &entrants);
+
+            let registration = Registration {
+                deposit: lottery.registration_deposit,
+                entropy_commitment,
+                entropy_revealed: false,
+                is_winner: false,
+                withdrawn: false,
+            };
+            self.registrations.insert((project_id, caller), &registration);
+        }
+
+        /// Reveals the entropy the caller committed to, mixing it into the draw.
+        ///
+        /// Registrants that don't reveal before `reveal_end` are left out of the draw.
+        #[ink(message)]
+        pub fn reveal_lottery_entropy(&mut self, project_id: u32, entropy: [u8; 32]) {
+            let caller = self.env().caller();
+            let mut lottery = self.lotteries.get(project_id).expect("Lottery not found");
+            let mut registration = self.registrations.get((project_id, caller)).expect("Not registered");
+
+            assert!(
+                lottery.registration_end <= self.time_now() && self.time_now() < lottery.reveal_end,
+                "Not in reveal phase"
+            );
+            assert!(!registration.entropy_revealed, "Entropy already revealed");
+            assert!(
+                Hash::from(self.env().hash_bytes::<Blake2x256>(&entropy)) == registration.entropy_commitment,
+                "Entropy does not match commitment"
+            );
+
+            registration.entropy_revealed = true;
+            self.registrations.insert((project_id, caller), &registration);
+
+            lottery.entropy = Hash::from(self.env().hash_encoded::<Blake2x256, _>(&(lottery.entropy, caller, entropy)));
+            self.lotteries.insert(project_id, &lottery);
+        }
+
+        #[ink(message)]
+        pub fn get_registration(&self, project_id: u32, account: AccountId) -> Option<Registration> {
+            self.registrations.get((project_id, account))
+        }
+
+        /// Reveals the committed seed and draws the winners.
+        ///
+        /// The seed is mixed with the entropy revealed by the registrants, then
+        /// used for a partial Fisher-Yates shuffle of the registrants that revealed.
+        /// Everything is committed before the first reveal, so the outcome can only
+        /// be influenced by withholding a reveal, which drops the registrant from
+        /// the draw. The draw has to happen before the presale starts.
+        #[ink(message)]
+        pub fn draw_lottery(&mut self, project_id: u32, seed: [u8; 32]) {
+            let project = self.projects.get(project_id).expect("Project not found");
+            let mut lottery = self.lotteries.get(project_id).expect("Lottery not found");
+
+            assert!(self.env().caller() == project.creator, "Only the creator can draw the lottery");
+            assert!(self.time_now() >= lottery.reveal_end, "Reveal not ended");
+            assert!(self.time_now() < project.start_time, "Presale already started");
+            assert!(!lottery.is_drawn, "Lottery already drawn");
+            assert!(
+                Hash::from(self.env().hash_bytes::<Blake2x256>(&seed)) == lottery.seed_commitment,
+                "Seed does not match commitment"
+            );
+
+            let mut entrants = self.lottery_entrants.get(project_id).unwrap_or_default();
+            entrants.retain(|entrant| {
+                self.registrations
+                    .get((project_id, *entrant))
+                    .is_some_and(|registration| registration.entropy_revealed && !registration.withdrawn)
+            });
+            let random = self.env().hash_encoded::<Blake2x256, _>(&(seed, lottery.entropy, &entrants));
+
+            let winners = entrants.len().min(lottery.winners as usize);
+            for i in 0..winners {
+                let draw = self.env().hash_encoded::<Blake2x256, _>(&(random, i as u32));
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&draw[..8]);
+                let remaining = (entrants.len() - i) as u64;
+                let j = i + (u64::from_le_bytes(bytes) % remaining) as usize;
+                entrants.swap(i, j);
+
+                let mut registration = self.registrations.get((project_id, entrants[i])).expect("Registration not found");
+                registration.is_winner = true;
+                self.registrations.insert((project_id, entrants[i]), &registration);
+            }
+
+            lottery.is_drawn = true;
+            self.lotteries.insert(project_id, &lottery);
+        }
+
+        /// Returns the caller's registration deposit.
+        ///
+        /// Losers can withdraw once the lottery is drawn, winners once the presale
+        /// has ended. If the creator never draws, everyone can withdraw after `start_time`.
+        #[ink(message)]
+        pub fn withdraw_registration_deposit(&mut self, project_id: u32) -> Balance {
+            self.guard.enter().expect("Reentrant call detected");
+
+            let caller = self.env().caller();
+            let project = self.projects.get(project_id).expect("Project not found");
+            let lottery = self.lotteries.get(project_id).expect("Lottery not found");
+            let mut registration = self.registrations.get((project_id, caller)).expect("Not registered");
+
+            assert!(!registration.withdrawn, "Deposit already withdrawn");
+            let withdrawable = if lottery.is_drawn {
+                !registration.is_winner || project.end_time <= self.time_now()
+            } else {
+                project.start_time <= self.time_now()
+            };
+            assert!(withdrawable, "Deposit is still locked");
+
+            registration.withdrawn = true;
+            self.registrations.insert((project_id, caller), &registration);
+
+            self.env().transfer(caller, registration.deposit).expect("Transfer failed");
+
+            self.guard.exit();
+            registration.deposit
+        }
+
+        /// Requires buyers to present an attestation signed by `verifier`.
+        ///
+        /// Only the project creator can change it, and only before the presale starts.
+        /// `None` removes the requirement.
+        #[ink(message)]
+        pub fn set_attestation_requirement(
+            &mut self,
+            project_id: u32,
+            requirement: Option<AttestationRequirement>,
+        ) {
+            let project = self.projects.get(project_id).expect("Project not found");
+
+            assert!(self.env().caller() == project.creator, "Only the creator can configure attestations");
+            assert!(project.start_time > self.time_now(), "Presale already started");
+
+            if let Some(requirement) = requirement {
+                self.attestation_requirements.insert(project_id, &requirement);
+            } else {
+                self.attestation_requirements.remove(project_id);
+            }
+        }
+
+        #[ink(message)]
+        pub fn get_attestation_requirement(&self, project_id: u32) -> Option<AttestationRequirement> {
+            self.attestation_requirements.get(project_id)
+        }
+
+        /// Returns the hash the verifier signs to attest `buyer` for a project.
+        #[ink(message)]
+        pub fn attestation_hash(
+            &self,
+            project_id: u32,
+            buyer: AccountId,
+            expires_at: Timestamp,
+            flags: u32,
+        ) -> [u8; 32] {
+            self.env()
+                .hash_encoded::<Blake2x256, _>(&(self.env().account_id(), project_id, buyer, expires_at, flags))
+        }
+
+        fn verify_attestation(
+            &self,
+            project_id: u32,
+            buyer: AccountId,
+            requirement: &AttestationRequirement,
+            attestation: &Attestation,
+        ) {
+            assert!(attestation.expires_at > self.time_now(), "Attestation expired");
+            assert!(
+                attestation.flags & requirement.required_flags == requirement.required_flags,
+                "Attestation flags not satisfied"
+            );
+
+            let message = self.attestation_hash(project_id, buyer, attestation.expires_at, attestation.flags);
+            let (VerifierKey::Ecdsa(key), AttestationSignature::Ecdsa(signature)) =
+                (&requirement.verifier, &attestation.signature);
+            let is_valid = self.env().ecdsa_recover(signature, &message).ok() == Some(*key);
+            assert!(is_valid, "Invalid attestation signature");
+        }
+
+        /// Sets the share of referred contributions paid to referrers.
+        ///
+        /// Only the project creator can change it, and only before the presale starts.
+        #[ink(message)]
+        pub fn set_referral_percentage(
+            &mut self,
+            project_id: u32,
+            referral_percentage: u8,
+        ) {
+            let mut project = self.projects.get(project_id).expect("Project not found");
+
+            assert!(self.env().caller() == project.creator, "Only the creator can configure referrals");
+            assert!(project.start_time > self.time_now(), "Presale already started");
+            assert!(referral_percentage <= MAX_REFERRAL_PERCENTAGE, "Referral percentage too high");
+
+            project.referral_percentage = referral_percentage;
+            self.projects.insert(project_id, &project);
+        }
+
+        #[ink(message, payable)]
+        pub fn join_project_presale(
+            &mut self,
+            project_id: u32,
+            buy_token_amount: Balance,
+            referrer: Option<AccountId>,
+            attestation: Option<Attestation>,
+        ) {
+            self.guard.enter().expect("Reentrant call detected");
+
+            let caller = self.env().caller();
+            let mut project = self.projects.get(project_id).expect("Project not found");
+
+            assert!(referrer != Some(caller), "Self-referral is not allowed");
+            self.admit_purchase(project_id, &project, caller, buy_token_amount, attestation);
+
+            let cost = self.calculate_price(project.presaled_amount, buy_token_amount);
+            let excess = self.record_purchase(project_id, &mut project, caller, buy_token_amount, cost);
+
+            let mut contribution = self.contributions.get((project_id, caller)).unwrap_or_default();
+            if let Some(referrer) = contribution.referrer.or(referrer) {
+                let reward = cost
+                    .checked_mul(project.referral_percentage.into())
+                    .expect("Invalid Operation")
+                    / 100;
+
+                let mut stats = self.referrals.get((project_id, referrer)).unwrap_or_default();
+                stats.referred_contributions = stats.referred_contributions.checked_add(1).expect("Invalid Operation");
+                stats.referred_amount = stats.referred_amount.checked_add(cost).expect("Invalid Operation");
+                stats.reward = stats.reward.checked_add(reward).expect("Invalid Operation");
+                self.referrals.insert((project_id, referrer), &stats);
+
+                contribution.referrer = Some(referrer);
+                contribution.referred_purchases = contribution.referred_purchases.checked_add(1).expect("Invalid Operation");
+                contribution.referred_amount = contribution.referred_amount.checked_add(cost).expect("Invalid Operation");
+                contribution.referral_reward = contribution.referral_reward.checked_add(reward).expect("Invalid Operation");
+                self.contributions.insert((project_id, caller), &contribution);
+            }
+
+            self.projects.insert(project_id, &project);
+            self.refund_excess(caller, excess);
+
+            self.guard.exit();
+        }
+
+        // Checks the timing, tier, lottery, attestation and anti-sniping gates of a
+        // purchase of `buy_token_amount` tokens by `buyer`.
+        fn admit_purchase(
+            &mut self,
+            project_id: u32,
+            project: &Project,
+            buyer: AccountId,
+            buy_token_amount: Balance,
+            attestation: Option<Attestation>,
+        ) {
+            let contribution = self.contributions.get((project_id, buyer)).unwrap_or_default();
+
+            // Tiered presales are only open to stakers, who may start buying
+            // `early_access` before `start_time`, up to their tier's allocation.
+            if self.project_tiers.contains(project_id) {
+                let (_, tier) = self.tier_of(project_id, buyer).expect("No allocation tier");
+                assert!(project.start_time.saturating_sub(tier.early_access) <= self.time_now(), "Presale not started");
+                assert!(
+                    contribution.token_amount.checked_add(buy_token_amount) <= Some(tier.max_allocation),
+                    "Tier allocation exceeded"
+                );
+            } else {
+                assert!(project.start_time <= self.time_now(), "Presale not started");
+            }
+            assert!(project.end_time > self.time_now(), "Presale ended");
+
+            // A lottery that wasn't drawn in time is void once the presale starts.
+            if let Some(lottery) = self.lotteries.get(project_id) {
+                if lottery.is_drawn {
+                    let registration = self.registrations.get((project_id, buyer)).expect("Not registered");
+                    assert!(registration.is_winner, "Not a lottery winner");
+                    assert!(
+                        contribution.token_amount.checked_add(buy_token_amount) <= Some(lottery.ticket_size),
+                        "Lottery ticket size exceeded"
+                    );
+                } else {
+                    assert!(project.start_time <= self.time_now(), "Lottery not drawn");
+                }
+            }
+
+            if let Some(requirement) = self.attestation_requirements.get(project_id) {
+                let attestation = attestation.expect("Attestation required");
+                self.verify_attestation(project_id, buyer, &requirement, &attestation);
+            }
+
+            if let Some(anti_sniping) = self.anti_sniping.get(project_id) {
+                self.enforce_anti_sniping(project_id, &anti_sniping, buyer, buy_token_amount);
+            }
+        }
+
+        /// Sets the anti-sniping limits of a project.
+        ///
+        /// Only the project creator can change them, and only before the presale starts.
+        /// `None` removes all limits.
+        #[ink(message)]
+        pub fn set_anti_sniping(
+            &mut self,
+            project_id: u32,
+            anti_sniping: Option<AntiSniping>,
+        ) {
+            let project = self.projects.get(project_id).expect("Project not found");
+
+            assert!(self.env().caller() == project.creator, "Only the creator can configure anti-sniping");
+            assert!(project.start_time > self.time_now(), "Presale already started");
+
+            if let Some(anti_sniping) = anti_sniping {
+                if let Some(ramp) = &anti_sniping.ramp {
+                    assert!(ramp.initial_max_buy <= ramp.final_max_buy, "Max buy ramp must increase");
+                }
+                self.anti_sniping.insert(project_id, &anti_sniping);
+            } else {
+                self.anti_sniping.remove(project_id);
+            }
+        }
+
+        #[ink(message)]
+        pub fn get_anti_sniping(&self, project_id: u32) -> Option<AntiSniping> {
+            self.anti_sniping.get(project_id)
+        }
+
+        /// Returns the current max-buy of a project, `None` if unlimited.
+        #[ink(message)]
+        pub fn current_max_buy(&self, project_id: u32) -> Option<Balance> {
+            let project = self.projects.get(project_id)?;
+            let ramp = self.anti_sniping.get(project_id)?.ramp?;
+
+            let elapsed = self.time_now().saturating_sub(project.start_time);
+            if elapsed >= ramp.duration {
+                return None;
+            }
+
+            let growth = (ramp.final_max_buy - ramp.initial_max_buy)
+                .checked_mul(elapsed.into())
+                .expect("Invalid Operation")
+                / Balance::from(ramp.duration);
+            Some(ramp.initial_max_buy + growth)
+        }
+
+        // Checks the per-block cap, cooldown and max-buy ramp and books the purchase against them.
+        fn enforce_anti_sniping(
+            &mut self,
+            project_id: u32,
+            anti_sniping: &AntiSniping,
+            buyer: AccountId,
+            buy_token_amount: Balance,
+        ) {
+            if let Some(max_tokens_per_block) = anti_sniping.max_tokens_per_block {
+                let block = self.env().block_number();
+                let sold = match self.block_sales.get(project_id) {
+                    Some((sale_block, sold)) if sale_block == block => sold,
+                    _ => 0,
+                };
+                let sold = sold.checked_add(buy_token_amount).expect("Invalid Operation");
+                assert!(sold <= max_tokens_per_block, "Block purchase cap reached");
+                self.block_sales.insert(project_id, &(block, sold));
+            }
+
+            if let Some(cooldown) = anti_sniping.cooldown {
+                if let Some(last_purchase) = self.last_purchases.get((project_id, buyer)) {
+                    assert!(last_purchase.saturating_add(cooldown) <= self.time_now(), "Purchase cooldown active");
+                }
+                self.last_purchases.insert((project_id, buyer), &self.time_now());
+            }
+
+            if let Some(max_buy) = self.current_max_buy(project_id) {
+                assert!(buy_token_amount <= max_buy, "Max buy exceeded");
+            }
+        }
+
+        /// Backs out of a running presale.
+        ///
+        /// Returns the paid amount minus the project's withdrawal penalty, which
+        /// goes to the creator or the treasury. The contributor's tokens are
+        /// returned to the curve, rolling its price back.
+        #[ink(message)]
+        pub fn withdraw_contribution(
+            &mut self,
+            project_id: u32,
+        ) -> Balance {
+            self.guard.enter().expect("Reentrant call detected");
+
+            let caller = self.env().caller();
+            let mut project = self.projects.get(project_id).expect("Project not found");
+            let contribution = self.contributions.get((project_id, caller)).expect("No contribution");
+
+            assert!(project.end_time > self.time_now() && !project.is_finished, "Presale ended");
+
+            let penalty = contribution
+                .paid_amount
+                .checked_mul(project.withdrawal_penalty.percentage.into())
+                .expect("Invalid Operation")
+                / 100;
+            let refund = contribution.paid_amount.checked_sub(penalty).expect("Invalid Operation");
+            let penalty_recipient = match project.withdrawal_penalty.recipient {
+                PenaltyRecipient::Creator => project.creator,
+                PenaltyRecipient::Treasury => self.treasury.expect("Treasury not set"),
+            };
+
+            project.presaled_amount = project.presaled_amount.checked_sub(contribution.token_amount).expect("Invalid Operation");
+            project.raised_amount = project.raised_amount.checked_sub(contribution.paid_amount).expect("Invalid Operation");
+            project.contributors_count = project.contributors_count.checked_sub(1).expect("Invalid Operation");
+            self.projects.insert(project_id, &project);
+
+            if let Some(referrer) = contribution.referrer {
+                let mut stats = self.referrals.get((project_id, referrer)).unwrap_or_default();
+                stats.referred_contributions = stats.referred_contributions.saturating_sub(contribution.referred_purchases);
+                stats.referred_amount = stats.referred_amount.saturating_sub(contribution.referred_amount);
+                stats.reward = stats.reward.saturating_sub(contribution.referral_reward);
+                self.referrals.insert((project_id, referrer), &stats);
+            }
+            self.contributions.remove((project_id, caller));
+
+            self.env().transfer(caller, refund).expect("Transfer failed");
+            if penalty > 0 {
+                self.env().transfer(penalty_recipient, penalty).expect("Transfer failed");
+            }
+
+            self.env().emit_event(ContributionWithdrawn {
+                project_id,
+                contributor: caller,
+                token_amount: contribution.token_amount,
+                refund,
+                penalty,
+            });
+
+            self.guard.exit();
+            refund
+        }
+
+        /// Sets the penalty kept from contributors withdrawing before the presale ends.
+        ///
+        /// Only the project creator can change it, and only before the presale starts.
+        #[ink(message)]
+        pub fn set_withdrawal_penalty(
+            &mut self,
+            project_id: u32,
+            withdrawal_penalty: WithdrawalPenalty,
+        ) {
+            let mut project = self.projects.get(project_id).expect("Project not found");
+
+            assert!(self.env().caller() == project.creator, "Only the creator can configure the withdrawal penalty");
+            assert!(project.start_time > self.time_now(), "Presale already started");
+            assert!(withdrawal_penalty.percentage <= MAX_WITHDRAWAL_PENALTY, "Withdrawal penalty too high");
+            assert!(
+                withdrawal_penalty.recipient != PenaltyRecipient::Treasury || self.treasury.is_some(),
+                "Treasury not set"
+            );
+
+            project.withdrawal_penalty = withdrawal_penalty;
+            self.projects.insert(project_id, &project);
+        }
+
+        #[ink(message)]
+        pub fn set_treasury(&mut self, treasury: AccountId) {
+            assert!(self.env().caller() == self.owner, "Only the owner can change the treasury");
+            self.treasury = Some(treasury);
+        }
+
+        #[ink(message)]
+        pub fn get_treasury(&self) -> Option<AccountId> {
+            self.treasury
+        }
+
+        /// Buys the allocation of a voucher signed by the project's voucher signer,
+        /// paying the voucher's `price` instead of the bonding curve price.
+        ///
+        /// The purchase passes the same tier, lottery, attestation and anti-sniping
+        /// gates as `join_project_presale`.
+        #[ink(message, payable)]
+        pub fn redeem_voucher(
+            &mut self,
+            project_id: u32,
+            voucher: Voucher,
+            signature: [u8; 65],
+            attestation: Option<Attestation>,
+        ) {
+            self.guard.enter().expect("Reentrant call detected");
+
+            let caller = self.env().caller();
+            let mut project = self.projects.get(project_id).expect("Project not found");
+            let signer = self.voucher_signers.get(project_id).expect("Vouchers not enabled");
+
+            assert!(voucher.deadline > self.time_now(), "Voucher expired");
+            assert!(!self.used_voucher_nonces.contains((project_id, caller, voucher.nonce)), "Voucher already used");
+
+            let message = self.voucher_hash(project_id, caller, voucher.clone());
+            assert!(
+                self.env().ecdsa_recover(&signature, &message).ok() == Some(signer),
+                "Invalid voucher signature"
+            );
+
+            self.admit_purchase(project_id, &project, caller, voucher.amount, attestation);
+            self.used_voucher_nonces.insert((project_id, caller, voucher.nonce), &());
+            let excess = self.record_purchase(project_id, &mut project, caller, voucher.amount, voucher.price);
+            self.projects.insert(project_id, &project);
+            self.refund_excess(caller, excess);
+
+            self.guard.exit();
+        }
+
+        /// Sets the compressed ECDSA public key vouchers of a project must be signed with.
+        ///
+        /// Only the project creator can change it, and only before the presale starts.
+        /// `None` disables vouchers.
+        #[ink(message)]
+        pub fn set_voucher_signer(
+            &mut self,
+            project_id: u32,
+            signer: Option<[u8; 33]>,
+        ) {
+            let project = self.projects.get(project_id).expect("Project not found");
+
+            assert!(self.env().caller() == project.creator, "Only the creator can configure vouchers");
+            assert!(project.start_time > self.time_now(), "Presale already started");
+
+            if let Some(signer) = signer {
+                self.voucher_signers.insert(project_id, &signer);
+            } else {
+                self.voucher_signers.remove(project_id);
+            }
+        }
+
+        #[ink(message)]
+        pub fn get_voucher_signer(&self, project_id: u32) -> Option<[u8; 33]> {
+            self.voucher_signers.get(project_id)
+        }
+
+        #[ink(message)]
+        pub fn is_voucher_used(&self, project_id: u32, buyer: AccountId, nonce: u64) -> bool {
+            self.used_voucher_nonces.contains((project_id, buyer, nonce))
+        }
+
+        /// Returns the hash the voucher signer signs to issue `voucher` to `buyer`.
+        #[ink(message)]
+        pub fn voucher_hash(
+            &self,
+            project_id: u32,
+            buyer: AccountId,
+            voucher: Voucher,
+        ) -> [u8; 32] {
+            self.env()
+                .hash_encoded::<Blake2x256, _>(&(self.env().account_id(), project_id, buyer, voucher))
+        }
+
+        // Books `buy_token_amount` tokens bought for `cost` on the project and
+        // on the buyer's contribution, and returns the part of the payment above
+        // `cost`. The caller stores `project` and refunds the excess afterwards.
+        fn record_purchase(
+            &mut self,
+            project_id: u32,
+            project: &mut Project,
+            buyer: AccountId,
+            buy_token_amount: Balance,
+            cost: Balance,
+        ) -> Balance {
+            // Buyers may overpay, as the price moves with every purchase in the block.
+            let excess = self.env().transferred_value().checked_sub(cost).expect("Insufficient payment");
+            assert!(project.presaled_amount.checked_add(buy_token_amount) < Some(project.total_presale_token_amount), "Insufficient amount");
+
+            project.presaled_amount = project.presaled_amount.checked_add(buy_token_amount).expect("Invalid Operation");
+            project.raised_amount = project.raised_amount.checked_add(cost).expect("Invalid Operation");
+
+            let mut contribution = self.contributions.get((project_id, buyer)).unwrap_or_else(|| {
+                project.contributors_count = project.contributors_count.checked_add(1).expect("Invalid Operation");
+                Contribution::default()
+            });
+            contribution.token_amount = contribution.token_amount.checked_add(buy_token_amount).expect("Invalid Operation");
+            contribution.paid_amount = contribution.paid_amount.checked_add(cost).expect("Invalid Operation");
+            self.contributions.insert((project_id, buyer), &contribution);
+            excess
+        }
+
+        fn refund_excess(&mut self, buyer: AccountId, excess: Balance) {
+            if excess > 0 {
+                self.env().transfer(buyer, excess).expect("Transfer failed");
+            }
+        }
+
+        #[ink(message)]
+        pub fn get_contribution(
+            &self,
+            project_id: u32,
+            contributor: AccountId,
+        ) -> Contribution {
+            self.contributions.get((project_id, contributor)).unwrap_or_default()
+        }
+
+        #[ink(message)]
+        pub fn get_referral_stats(
+            &self,
+            project_id: u32,
+            referrer: AccountId,
+        ) -> ReferralStats {
+            self.referrals.get((project_id, referrer)).unwrap_or_default()
+        }
+
+        /// Pays out the caller's referral rewards of a successfully finished presale.
+        #[ink(message)]
+        pub fn claim_referral_rewards(
+            &mut self,
+            project_id: u32,
+        ) -> Balance {
+            self.guard.enter().expect("Reentrant call detected");
+
+            let caller = self.env().caller();
+            let project = self.projects.get(project_id).expect("Project not found");
+            assert!(project.is_finished && project.is_successful, "Presale not successful");
+
+            let mut stats = self.referrals.get((project_id, caller)).expect("No referral rewards");
+            assert!(!stats.claimed, "Referral rewards already claimed");
+
+            stats.claimed = true;
+            self.referrals.insert((project_id, caller), &stats);
+
+            self.env().transfer(caller, stats.reward).expect("Transfer failed");
+
+            self.guard.exit();
+            stats.reward
+        }
+
+        /// Finishes an ended presale and applies the project's unsold token policy
+        /// to the presale tokens nobody bought.
+        #[ink(message)]
+        pub fn finish_presale(
+            &mut self,
+            project_id: u32,
+        ) {
+            self.guard.enter().expect("Reentrant call detected");
+
+            let mut project = self.projects.get(project_id).expect("Project not found");
+
+            assert!(project.end_time <= self.time_now(), "Presale not finished");
+            assert!(!project.is_finished, "Presale already finished");
+            project.is_finished = true;
+            project.is_successful = Self::soft_cap_reached(&project);
+            self.projects.insert(project_id, &project);
+
+            let unsold = project.total_presale_token_amount.saturating_sub(project.presaled_amount);
+            if unsold > 0 {
+                let recipient = match project.unsold_token_policy {
+                    UnsoldTokenPolicy::Burn => None,
+                    UnsoldTokenPolicy::ReturnToCreator => Some(project.creator),
+                    UnsoldTokenPolicy::LiquidityPool => Some(self.liquidity_pool.expect("Liquidity pool not set")),
+                };
+
+                if let Some(recipient) = recipient {
+                    let mut token: TokenContractRef = ink::env::call::FromAccountId::from_account_id(project.token);
+                    token.transfer(recipient, unsold, Vec::new()).expect("Token transfer failed");
+                } else {
+                    let mut burnable: ink::contract_ref!(PSP22Burnable) = project.token.into();
+                    burnable.burn(unsold).expect("Token burn failed");
+                }
+
+                self.env().emit_event(UnsoldTokensHandled {
+                    project_id,
+                    policy: project.unsold_token_policy,
+                    amount: unsold,
+                    recipient,
+                });
+            }
+
+            self.guard.exit();
+        }
+
+        #[ink(message)]
+        pub fn set_liquidity_pool(&mut self, liquidity_pool: AccountId) {
+            assert!(self.env().caller() == self.owner, "Only the owner can change the liquidity pool");
+            self.liquidity_pool = Some(liquidity_pool);
+        }
+
+        #[ink(message)]
+        pub fn get_liquidity_pool(&self) -> Option<AccountId> {
+            self.liquidity_pool
+        }
+
+        /// Moves the end of a running presale to `new_end_time`.
+        ///
+        /// A presale can be extended only once, by at most `MAX_PRESALE_EXTENSION`.
+        #[ink(message)]
+        pub fn extend_presale(
+            &mut self,
+            project_id: u32,
+            new_end_time: Timestamp,
+        ) {
+            let mut project = self.projects.get(project_id).expect("Project not found");
+
+            assert!(self.env().caller() == project.creator, "Only the creator can extend the presale");
+            assert!(!project.is_extended, "Presale already extended");
+            assert!(project.end_time > self.time_now(), "Presale ended");
+            assert!(new_end_time > project.end_time, "New end time must be later");
+            assert!(
+                new_end_time - project.end_time <= MAX_PRESALE_EXTENSION,
+                "Extension too long"
+            );
+
+            let previous_end_time = project.end_time;
+            project.end_time = new_end_time;
+            project.is_extended = true;
+            self.projects.insert(project_id, &project);
+
+            self.env().emit_event(PresaleExtended { project_id, previous_end_time, end_time: new_end_time });
+        }
+
+        /// Ends a running presale now, once its soft cap is reached.
+        #[ink(message)]
+        pub fn close_early(
+            &mut self,
+            project_id: u32,
+        ) {
+            let mut project = self.projects.get(project_id).expect("Project not found");
+
+            assert!(self.env().caller() == project.creator, "Only the creator can close the presale");
+            assert!(project.start_time <= self.time_now(), "Presale not started");
+            assert!(project.end_time > self.time_now(), "Presale ended");
+            assert!(Self::soft_cap_reached(&project), "Soft cap not reached");
+
+            project.end_time = self.time_now();
+            self.projects.insert(project_id, &project);
+
+            self.env().emit_event(PresaleClosedEarly {
+                project_id,
+                end_time: project.end_time,
+                raised_amount: project.raised_amount,
+            });
+        }
+
+        // Validates the allocations and returns their total amount.
+        fn allocated_amount(allocations: &[Allocation]) -> Balance {
+            assert!(allocations.len() <= MAX_ALLOCATIONS, "Too many allocations");
+
+            allocations.iter().fold(0, |total: Balance, allocation| {
+                assert!(allocation.amount > 0, "Allocation amount must be positive");
+                total.checked_add(allocation.amount).expect("Invalid Operation")
+            })
+        }
+
+        // A presale succeeds once a third of the intended amount is raised.
+        fn soft_cap_reached(project: &Project) -> bool {
+            project.intended_raise_amount / 3 <= project.raised_amount
+        }
+
+        #[ink(message)]
+        pub fn calculate_price(
+            &self,
+            presaled_amount: Balance,
+            buy_token_amount: Balance,
+        ) -> Balance {
+            let k: Balance = 1;
+            let c: Balance = 0;
+
+            let current_price = k.checked_mul(presaled_amount).unwrap_or(0).checked_add(c).unwrap_or(0);
+            let next_price = k
+                .checked_mul(presaled_amount.checked_add(buy_token_amount).unwrap_or(0))
+                .unwrap_or(0)
+                .checked_add(c)
+                .unwrap_or(0);
+
+            current_price
+                .checked_add(next_price)
+                .unwrap_or(0)
+                .checked_mul(buy_token_amount)
+                .unwrap_or(0)
+                .checked_div(2)
+                .unwrap_or(0)
+        }
+
+        pub fn time_now(&self) -> Timestamp {
+            self.env().block_timestamp()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use ink::env::test::*;
+        use ink::env::DefaultEnvironment as E;
+
+        fn contract() -> PolkadotPresaleContract {
+            let acc = default_accounts::<E>();
+            set_callee::<E>(AccountId::from([0xff; 32]));
+            set_caller::<E>(acc.alice);
+            PolkadotPresaleContract::new(acc.django, acc.eve)
+        }
+
+        // Stores a project directly, skipping the token factory and lock calls.
+        fn add_project(contract: &mut PolkadotPresaleContract, creator: AccountId) -> u32 {
+            let project = Project {
+                token: AccountId::from([0x10; 32]),
+                total_presale_token_amount: 1_000,
+                presaled_amount: 0,
+                intended_raise_amount: 150,
+                raised_amount: 0,
+                start_time: 10,
+                end_time: 100,
+                creator,
+                token_lock: AccountId::from([0x11; 32]),
+                contributors_count: 0,
+                is_finished: false,
+                is_successful: false,
+                referral_percentage: 0,
+                is_extended: false,
+                withdrawal_penalty: WithdrawalPenalty::default(),
+                unsold_token_policy: UnsoldTokenPolicy::ReturnToCreator,
+            };
+            let project_id = contract.last_project_id + 1;
+            contract.last_project_id = project_id;
+            contract.projects.insert(project_id, &project);
+            project_id
+        }
+
+        /// Leaves no unsold tokens, since finishing would otherwise call the token contract.
+        fn sell_out(contract: &mut PolkadotPresaleContract, project_id: u32) {
+            let mut project = contract.projects.get(project_id).unwrap();
+            project.total_presale_token_amount = project.presaled_amount;
+            contract.projects.insert(project_id, &project);
+        }
+
+        fn advance_to(timestamp: Timestamp) {
+            set_block_timestamp::<E>(timestamp);
+        }
+
+        #[ink::test]
+        fn new_works() {
+            let acc = default_accounts::<E>();
+            let contract = contract();
+            assert_eq!(contract.get_token_factory(), acc.django);
+            assert_eq!(contract.get_token_lock(), acc.eve);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Only the owner can change the token factory")]
+        fn set_token_factory_requires_owner() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            set_caller::<E>(acc.bob);
+            contract.set_token_factory(acc.frank);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Only the owner can change the token lock")]
+        fn set_token_lock_requires_owner() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            set_caller::<E>(acc.bob);
+            contract.set_token_lock(acc.frank);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Self-referral is not allowed")]
+        fn self_referral_fails() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            advance_to(10);
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(50);
+            contract.join_project_presale(project_id, 10, Some(acc.bob), None);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Only the creator can configure referrals")]
+        fn set_referral_percentage_requires_creator() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+
+            set_caller::<E>(acc.bob);
+            contract.set_referral_percentage(project_id, 5);
+        }
+
+        #[ink::test]
+        fn referral_rewards_work() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            contract.set_referral_percentage(project_id, 10);
+            advance_to(10);
+
+            // Buying 10 tokens from an empty curve costs 50.
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(50);
+            contract.join_project_presale(project_id, 10, Some(acc.charlie), None);
+
+            let stats = contract.get_referral_stats(project_id, acc.charlie);
+            assert_eq!(stats.referred_contributions, 1);
+            assert_eq!(stats.referred_amount, 50);
+            assert_eq!(stats.reward, 5);
+            assert!(!stats.claimed);
+
+            advance_to(100);
+            sell_out(&mut contract, project_id);
+            contract.finish_presale(project_id);
+
+            set_caller::<E>(acc.charlie);
+            let contract_id = callee::<E>();
+            set_account_balance::<E>(contract_id, 1_000_000);
+            let before = get_account_balance::<E>(acc.charlie).unwrap();
+            assert_eq!(contract.claim_referral_rewards(project_id), 5);
+            assert_eq!(get_account_balance::<E>(acc.charlie).unwrap(), before + 5);
+            assert!(contract.get_referral_stats(project_id, acc.charlie).claimed);
+        }
+
+        fn tiers() -> Vec<Tier> {
+            vec![
+                Tier { min_stake: 100, min_staking_duration: 0, max_allocation: 10, early_access: 0 },
+                Tier { min_stake: 1_000, min_staking_duration: 5, max_allocation: 50, early_access: 5 },
+            ]
+        }
+
+        #[ink::test]
+        fn tier_of_uses_stake_amount_and_duration() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            contract.set_project_tiers(project_id, tiers());
+
+            assert_eq!(contract.get_tier(project_id, acc.bob), None);
+
+            contract.stakes.insert(acc.bob, &Stake { amount: 1_000, staked_at: 0 });
+            assert_eq!(contract.get_tier(project_id, acc.bob), Some(0));
+
+            advance_to(5);
+            assert_eq!(contract.get_tier(project_id, acc.bob), Some(1));
+        }
+
+        #[ink::test]
+        fn tiered_presale_allows_early_access() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            contract.set_project_tiers(project_id, tiers());
+            contract.stakes.insert(acc.bob, &Stake { amount: 1_000, staked_at: 0 });
+            advance_to(5);
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(50);
+            contract.join_project_presale(project_id, 10, None, None);
+            assert_eq!(contract.get_contribution(project_id, acc.bob).token_amount, 10);
+        }
+
+        #[ink::test]
+        fn cleared_tiers_open_presale_to_everyone() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            contract.set_project_tiers(project_id, tiers());
+            contract.set_project_tiers(project_id, Vec::new());
+            advance_to(10);
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(50);
+            contract.join_project_presale(project_id, 10, None, None);
+            assert_eq!(contract.get_contribution(project_id, acc.bob).token_amount, 10);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "No allocation tier")]
+        fn tiered_presale_rejects_non_stakers() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            contract.set_project_tiers(project_id, tiers());
+            advance_to(10);
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(50);
+            contract.join_project_presale(project_id, 10, None, None);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Tier allocation exceeded")]
+        fn tiered_presale_enforces_max_allocation() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            contract.set_project_tiers(project_id, tiers());
+            contract.stakes.insert(acc.bob, &Stake { amount: 100, staked_at: 0 });
+            advance_to(10);
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(1_000);
+            contract.join_project_presale(project_id, 11, None, None);
+        }
+
+        fn commit(secret: [u8; 32]) -> Hash {
+            let mut commitment = <Blake2x256 as ink::env::hash::HashOutput>::Type::default();
+            ink::env::hash_bytes::<Blake2x256>(&secret, &mut commitment);
+            Hash::from(commitment)
+        }
+
+        fn lottery_seed() -> ([u8; 32], Hash) {
+            let seed = [7u8; 32];
+            (seed, commit(seed))
+        }
+
+        // Registers `entrant` with `[entropy; 32]`.
+        fn register(contract: &mut PolkadotPresaleContract, project_id: u32, entrant: AccountId, entropy: u8) {
+            set_caller::<E>(entrant);
+            set_value_transferred::<E>(100);
+            contract.register_for_lottery(project_id, commit([entropy; 32]));
+        }
+
+        fn reveal(contract: &mut PolkadotPresaleContract, project_id: u32, entrant: AccountId, entropy: u8) {
+            set_caller::<E>(entrant);
+            set_value_transferred::<E>(0);
+            contract.reveal_lottery_entropy(project_id, [entropy; 32]);
+        }
+
+        #[ink::test]
+        fn lottery_draws_winners_and_refunds_losers() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            let (seed, commitment) = lottery_seed();
+            contract.set_project_lottery(project_id, 100, 10, 1, 5, 7, commitment);
+            set_account_balance::<E>(callee::<E>(), 1_000_000);
+
+            let entrants = [acc.bob, acc.charlie, acc.django];
+            for (entropy, entrant) in (0u8..).zip(entrants) {
+                register(&mut contract, project_id, entrant, entropy);
+            }
+            advance_to(5);
+            for (entropy, entrant) in (0u8..).zip(entrants) {
+                reveal(&mut contract, project_id, entrant, entropy);
+            }
+
+            advance_to(7);
+            set_caller::<E>(acc.alice);
+            contract.draw_lottery(project_id, seed);
+
+            let winners: Vec<AccountId> = [acc.bob, acc.charlie, acc.django]
+                .into_iter()
+                .filter(|entrant| contract.get_registration(project_id, *entrant).unwrap().is_winner)
+                .collect();
+            assert_eq!(winners.len(), 1);
+
+            let loser = [acc.bob, acc.charlie, acc.django]
+                .into_iter()
+                .find(|entrant| *entrant != winners[0])
+                .unwrap();
+            set_caller::<E>(loser);
+            set_value_transferred::<E>(0);
+            assert_eq!(contract.withdraw_registration_deposit(project_id), 100);
+
+            advance_to(10);
+            set_caller::<E>(winners[0]);
+            set_value_transferred::<E>(50);
+            contract.join_project_presale(project_id, 10, None, None);
+            assert_eq!(contract.get_contribution(project_id, winners[0]).token_amount, 10);
+        }
+
+        #[ink::test]
+        fn repeat_buyers_count_once() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            advance_to(10);
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(50);
+            contract.join_project_presale(project_id, 10, None, None);
+            set_value_transferred::<E>(150);
+            contract.join_project_presale(project_id, 10, None, None);
+
+            assert_eq!(contract.get_project(project_id).unwrap().contributors_count, 1);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Seed does not match commitment")]
+        fn lottery_rejects_wrong_seed() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            let (_, commitment) = lottery_seed();
+            contract.set_project_lottery(project_id, 100, 10, 1, 5, 7, commitment);
+
+            advance_to(7);
+            contract.draw_lottery(project_id, [8u8; 32]);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Presale already started")]
+        fn lottery_cannot_be_drawn_after_start() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            let (seed, commitment) = lottery_seed();
+            contract.set_project_lottery(project_id, 100, 10, 1, 5, 7, commitment);
+
+            advance_to(10);
+            contract.draw_lottery(project_id, seed);
+        }
+
+        #[ink::test]
+        fn lottery_draws_only_revealed_entrants() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            let (seed, commitment) = lottery_seed();
+            contract.set_project_lottery(project_id, 100, 10, 2, 5, 7, commitment);
+
+            register(&mut contract, project_id, acc.bob, 1);
+            register(&mut contract, project_id, acc.charlie, 2);
+            advance_to(5);
+            reveal(&mut contract, project_id, acc.charlie, 2);
+            assert_ne!(contract.get_lottery(project_id).unwrap().entropy, Hash::default());
+
+            advance_to(7);
+            set_caller::<E>(acc.alice);
+            contract.draw_lottery(project_id, seed);
+            assert!(!contract.get_registration(project_id, acc.bob).unwrap().is_winner);
+            assert!(contract.get_registration(project_id, acc.charlie).unwrap().is_winner);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Entropy does not match commitment")]
+        fn lottery_rejects_wrong_entropy() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            let (_, commitment) = lottery_seed();
+            contract.set_project_lottery(project_id, 100, 10, 1, 5, 7, commitment);
+
+            register(&mut contract, project_id, acc.bob, 1);
+            advance_to(5);
+            reveal(&mut contract, project_id, acc.bob, 2);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Creator cannot register")]
+        fn lottery_rejects_creator_registration() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            let (_, commitment) = lottery_seed();
+            contract.set_project_lottery(project_id, 100, 10, 1, 5, 7, commitment);
+
+            register(&mut contract, project_id, acc.alice, 1);
+        }
+
+        #[ink::test]
+        fn undrawn_lottery_opens_presale_at_start() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            let (_, commitment) = lottery_seed();
+            contract.set_project_lottery(project_id, 100, 10, 1, 5, 7, commitment);
+
+            advance_to(10);
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(50);
+            contract.join_project_presale(project_id, 10, None, None);
+            assert_eq!(contract.get_contribution(project_id, acc.bob).token_amount, 10);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Not registered")]
+        fn lottery_presale_rejects_unregistered_buyers() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            let (seed, commitment) = lottery_seed();
+            contract.set_project_lottery(project_id, 100, 10, 1, 5, 7, commitment);
+
+            advance_to(7);
+            contract.draw_lottery(project_id, seed);
+
+            advance_to(10);
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(50);
+            contract.join_project_presale(project_id, 10, None, None);
+        }
+
+        const KYC_FLAG: u32 = 0b01;
+        const NON_US_FLAG: u32 = 0b10;
+
+        fn ecdsa_verifier() -> (secp256k1::SecretKey, VerifierKey) {
+            let secret_key = secp256k1::SecretKey::from_slice(&[0x42; 32]).unwrap();
+            let public_key = secret_key.public_key(secp256k1::SECP256K1).serialize();
+            (secret_key, VerifierKey::Ecdsa(public_key))
+        }
+
+        fn ecdsa_sign(secret_key: &secp256k1::SecretKey, message: [u8; 32]) -> [u8; 65] {
+            let message = secp256k1::Message::from_digest_slice(&message).unwrap();
+            let (recovery_id, compact) = secp256k1::SECP256K1
+                .sign_ecdsa_recoverable(&message, secret_key)
+                .serialize_compact();
+            let mut signature = [0u8; 65];
+            signature[..64].copy_from_slice(&compact);
+            signature[64] = recovery_id.to_i32() as u8;
+            signature
+        }
+
+        fn attested_project(contract: &mut PolkadotPresaleContract) -> (u32, secp256k1::SecretKey) {
+            let acc = default_accounts::<E>();
+            let project_id = add_project(contract, acc.alice);
+            let (secret_key, verifier) = ecdsa_verifier();
+            contract.set_attestation_requirement(
+                project_id,
+                Some(AttestationRequirement { verifier, required_flags: KYC_FLAG }),
+            );
+            (project_id, secret_key)
+        }
+
+        #[ink::test]
+        fn valid_attestation_works() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let (project_id, secret_key) = attested_project(&mut contract);
+            advance_to(10);
+
+            let flags = KYC_FLAG | NON_US_FLAG;
+            let message = contract.attestation_hash(project_id, acc.bob, 50, flags);
+            let attestation = Attestation {
+                expires_at: 50,
+                flags,
+                signature: AttestationSignature::Ecdsa(ecdsa_sign(&secret_key, message)),
+            };
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(50);
+            contract.join_project_presale(project_id, 10, None, Some(attestation));
+            assert_eq!(contract.get_contribution(project_id, acc.bob).token_amount, 10);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Attestation required")]
+        fn missing_attestation_fails() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let (project_id, _) = attested_project(&mut contract);
+            advance_to(10);
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(50);
+            contract.join_project_presale(project_id, 10, None, None);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Attestation expired")]
+        fn expired_attestation_fails() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let (project_id, secret_key) = attested_project(&mut contract);
+            advance_to(10);
+
+            let message = contract.attestation_hash(project_id, acc.bob, 10, KYC_FLAG);
+            let attestation = Attestation {
+                expires_at: 10,
+                flags: KYC_FLAG,
+                signature: AttestationSignature::Ecdsa(ecdsa_sign(&secret_key, message)),
+            };
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(50);
+            contract.join_project_presale(project_id, 10, None, Some(attestation));
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Invalid attestation signature")]
+        fn attestation_for_other_buyer_fails() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let (project_id, secret_key) = attested_project(&mut contract);
+            advance_to(10);
+
+            let message = contract.attestation_hash(project_id, acc.charlie, 50, KYC_FLAG);
+            let attestation = Attestation {
+                expires_at: 50,
+                flags: KYC_FLAG,
+                signature: AttestationSignature::Ecdsa(ecdsa_sign(&secret_key, message)),
+            };
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(50);
+            contract.join_project_presale(project_id, 10, None, Some(attestation));
+        }
+
+        fn voucher_project(contract: &mut PolkadotPresaleContract) -> (u32, secp256k1::SecretKey) {
+            let acc = default_accounts::<E>();
+            let project_id = add_project(contract, acc.alice);
+            let (secret_key, signer) = ecdsa_verifier();
+            let VerifierKey::Ecdsa(signer) = signer;
+            contract.set_voucher_signer(project_id, Some(signer));
+            (project_id, secret_key)
+        }
+
+        #[ink::test]
+        fn voucher_redemption_uses_voucher_price() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let (project_id, secret_key) = voucher_project(&mut contract);
+            advance_to(10);
+
+            let voucher = Voucher { amount: 100, price: 7, deadline: 50, nonce: 1 };
+            let signature = ecdsa_sign(&secret_key, contract.voucher_hash(project_id, acc.bob, voucher.clone()));
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(7);
+            contract.redeem_voucher(project_id, voucher, signature, None);
+
+            assert_eq!(
+                contract.get_contribution(project_id, acc.bob),
+                Contribution { token_amount: 100, paid_amount: 7, ..Default::default() }
+            );
+            assert!(contract.is_voucher_used(project_id, acc.bob, 1));
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Voucher already used")]
+        fn voucher_nonce_cannot_be_reused() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let (project_id, secret_key) = voucher_project(&mut contract);
+            advance_to(10);
+
+            let voucher = Voucher { amount: 100, price: 7, deadline: 50, nonce: 1 };
+            let signature = ecdsa_sign(&secret_key, contract.voucher_hash(project_id, acc.bob, voucher.clone()));
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(7);
+            contract.redeem_voucher(project_id, voucher.clone(), signature, None);
+            contract.redeem_voucher(project_id, voucher, signature, None);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Invalid voucher signature")]
+        fn voucher_for_other_buyer_fails() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let (project_id, secret_key) = voucher_project(&mut contract);
+            advance_to(10);
+
+            let voucher = Voucher { amount: 100, price: 7, deadline: 50, nonce: 1 };
+            let signature = ecdsa_sign(&secret_key, contract.voucher_hash(project_id, acc.charlie, voucher.clone()));
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(7);
+            contract.redeem_voucher(project_id, voucher, signature, None);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Presale already started")]
+        fn voucher_signer_is_frozen_once_started() {
+            let mut contract = contract();
+            let (project_id, _) = voucher_project(&mut contract);
+            advance_to(10);
+
+            contract.set_voucher_signer(project_id, None);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Attestation required")]
+        fn voucher_redemption_requires_attestation() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let (project_id, secret_key) = voucher_project(&mut contract);
+            let (_, verifier) = ecdsa_verifier();
+            contract.set_attestation_requirement(
+                project_id,
+                Some(AttestationRequirement { verifier, required_flags: KYC_FLAG }),
+            );
+            advance_to(10);
+
+            let voucher = Voucher { amount: 100, price: 7, deadline: 50, nonce: 1 };
+            let signature = ecdsa_sign(&secret_key, contract.voucher_hash(project_id, acc.bob, voucher.clone()));
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(7);
+            contract.redeem_voucher(project_id, voucher, signature, None);
+        }
+
+        #[ink::test]
+        fn project_view_includes_metadata_and_moderation() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+
+            let metadata = ProjectMetadata {
+                description: String::from("A presale"),
+                website: String::from("https://example.com"),
+                socials: vec![String::from("https://x.com/example")],
+                whitepaper_hash: Some(Hash::from([0x01; 32])),
+                ipfs_cid: String::from("bafy"),
+            };
+            contract.update_project_metadata(project_id, metadata.clone());
+            contract.set_project_moderation(project_id, Moderation { verified: true, hidden: false });
+            assert_eq!(recorded_events().count(), 2);
+
+            let view = contract.get_project(project_id).unwrap();
+            assert_eq!(view.creator, acc.alice);
+            assert_eq!(view.metadata, metadata);
+            assert!(view.moderation.verified);
+            assert!(!view.moderation.hidden);
+            assert_eq!(contract.get_project(project_id + 1), None);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Presale already started")]
+        fn metadata_is_frozen_after_start() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            advance_to(10);
+
+            contract.update_project_metadata(project_id, ProjectMetadata::default());
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Only the owner can moderate projects")]
+        fn moderation_requires_owner() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+
+            set_caller::<E>(acc.bob);
+            contract.set_project_moderation(project_id, Moderation { verified: true, hidden: false });
+        }
+
+        #[ink::test]
+        fn extend_presale_works_once() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            advance_to(50);
+
+            contract.extend_presale(project_id, 200);
+            let view = contract.get_project(project_id).unwrap();
+            assert_eq!(view.end_time, 200);
+            assert!(view.is_extended);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Presale already extended")]
+        fn extend_presale_fails_twice() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+
+            contract.extend_presale(project_id, 200);
+            contract.extend_presale(project_id, 300);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Extension too long")]
+        fn extend_presale_is_bounded() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+
+            contract.extend_presale(project_id, 100 + MAX_PRESALE_EXTENSION + 1);
+        }
+
+        #[ink::test]
+        fn close_early_after_soft_cap() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            advance_to(10);
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(50);
+            contract.join_project_presale(project_id, 10, None, None);
+
+            advance_to(20);
+            set_caller::<E>(acc.alice);
+            contract.close_early(project_id);
+            assert_eq!(contract.get_project(project_id).unwrap().end_time, 20);
+
+            sell_out(&mut contract, project_id);
+            contract.finish_presale(project_id);
+            assert!(contract.get_project(project_id).unwrap().is_successful);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Soft cap not reached")]
+        fn close_early_requires_soft_cap() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            advance_to(10);
+
+            contract.close_early(project_id);
+        }
+
+        #[ink::test]
+        fn withdraw_contribution_rolls_back_and_applies_penalty() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            contract.set_referral_percentage(project_id, 10);
+            contract.set_withdrawal_penalty(
+                project_id,
+                WithdrawalPenalty { percentage: 10, recipient: PenaltyRecipient::Creator },
+            );
+            set_account_balance::<E>(callee::<E>(), 1_000_000);
+            advance_to(10);
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(50);
+            contract.join_project_presale(project_id, 10, Some(acc.charlie), None);
+            set_caller::<E>(acc.django);
+            set_value_transferred::<E>(150);
+            contract.join_project_presale(project_id, 10, None, None);
+
+            let creator_before = get_account_balance::<E>(acc.alice).unwrap();
+            let bob_before = get_account_balance::<E>(acc.bob).unwrap();
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(0);
+            assert_eq!(contract.withdraw_contribution(project_id), 45);
+            assert_eq!(get_account_balance::<E>(acc.bob).unwrap(), bob_before + 45);
+            assert_eq!(get_account_balance::<E>(acc.alice).unwrap(), creator_before + 5);
+
+            let view = contract.get_project(project_id).unwrap();
+            assert_eq!(view.presaled_amount, 10);
+            assert_eq!(view.raised_amount, 150);
+            assert_eq!(view.contributors_count, 1);
+            assert_eq!(contract.get_contribution(project_id, acc.bob), Contribution::default());
+            assert_eq!(contract.get_referral_stats(project_id, acc.charlie), ReferralStats::default());
+            assert_eq!(contract.calculate_price(view.presaled_amount, 10), 150);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Presale ended")]
+        fn withdraw_contribution_fails_after_end() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            advance_to(10);
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(50);
+            contract.join_project_presale(project_id, 10, None, None);
+
+            advance_to(100);
+            contract.withdraw_contribution(project_id);
+        }
+
+        fn anti_sniping_project(contract: &mut PolkadotPresaleContract, anti_sniping: AntiSniping) -> u32 {
+            let acc = default_accounts::<E>();
+            let project_id = add_project(contract, acc.alice);
+            contract.set_anti_sniping(project_id, Some(anti_sniping));
+            advance_to(10);
+            project_id
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Block purchase cap reached")]
+        fn per_block_cap_is_enforced() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = anti_sniping_project(
+                &mut contract,
+                AntiSniping { max_tokens_per_block: Some(15), cooldown: None, ramp: None },
+            );
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(50);
+            contract.join_project_presale(project_id, 10, None, None);
+            set_caller::<E>(acc.charlie);
+            set_value_transferred::<E>(150);
+            contract.join_project_presale(project_id, 10, None, None);
+        }
+
+        #[ink::test]
+        fn per_block_cap_resets_next_block() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = anti_sniping_project(
+                &mut contract,
+                AntiSniping { max_tokens_per_block: Some(15), cooldown: None, ramp: None },
+            );
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(50);
+            contract.join_project_presale(project_id, 10, None, None);
+            advance_block::<E>();
+            set_caller::<E>(acc.charlie);
+            // The next 10 tokens cost 150 on the curve.
+            set_value_transferred::<E>(150);
+            contract.join_project_presale(project_id, 10, None, None);
+            assert_eq!(contract.get_project(project_id).unwrap().presaled_amount, 20);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Purchase cooldown active")]
+        fn cooldown_is_enforced() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = anti_sniping_project(
+                &mut contract,
+                AntiSniping { max_tokens_per_block: None, cooldown: Some(5), ramp: None },
+            );
+
+            // The first token of the curve is free, the second costs 1.
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(0);
+            contract.join_project_presale(project_id, 1, None, None);
+            advance_to(14);
+            set_value_transferred::<E>(1);
+            contract.join_project_presale(project_id, 1, None, None);
+        }
+
+        #[ink::test]
+        fn max_buy_ramps_up() {
+            let mut contract = contract();
+            let project_id = anti_sniping_project(
+                &mut contract,
+                AntiSniping {
+                    max_tokens_per_block: None,
+                    cooldown: None,
+                    ramp: Some(MaxBuyRamp { duration: 20, initial_max_buy: 10, final_max_buy: 50 }),
+                },
+            );
+
+            assert_eq!(contract.current_max_buy(project_id), Some(10));
+            advance_to(20);
+            assert_eq!(contract.current_max_buy(project_id), Some(30));
+            advance_to(30);
+            assert_eq!(contract.current_max_buy(project_id), None);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Max buy exceeded")]
+        fn max_buy_is_enforced() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = anti_sniping_project(
+                &mut contract,
+                AntiSniping {
+                    max_tokens_per_block: None,
+                    cooldown: None,
+                    ramp: Some(MaxBuyRamp { duration: 20, initial_max_buy: 10, final_max_buy: 50 }),
+                },
+            );
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(1_000);
+            contract.join_project_presale(project_id, 11, None, None);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Presale already finished")]
+        fn finish_presale_only_once() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            advance_to(100);
+            sell_out(&mut contract, project_id);
+            contract.finish_presale(project_id);
+            contract.finish_presale(project_id);
+        }
+
+        #[ink::test]
+        fn set_liquidity_pool_works() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            assert_eq!(contract.get_liquidity_pool(), None);
+            contract.set_liquidity_pool(acc.django);
+            assert_eq!(contract.get_liquidity_pool(), Some(acc.django));
+        }
+
+        fn allocation(amount: Balance) -> Allocation {
+            Allocation {
+                category: AllocationCategory::Team,
+                beneficiary: default_accounts::<E>().bob,
+                amount,
+                cliff: 10,
+                vesting_duration: 100,
+            }
+        }
+
+        #[ink::test]
+        fn allocated_amount_works() {
+            let allocations = vec![allocation(100), allocation(250)];
+            assert_eq!(PolkadotPresaleContract::allocated_amount(&allocations), 350);
+            assert_eq!(PolkadotPresaleContract::allocated_amount(&[]), 0);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Allocation amount must be positive")]
+        fn empty_allocation_fails() {
+            PolkadotPresaleContract::allocated_amount(&[allocation(0)]);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Too many allocations")]
+        fn too_many_allocations_fail() {
+            PolkadotPresaleContract::allocated_amount(&vec![allocation(1); MAX_ALLOCATIONS + 1]);
+        }
+
+        #[ink::test]
+        fn overpayment_is_refunded() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            set_account_balance::<E>(callee::<E>(), 1_000_000);
+            advance_to(10);
+
+            let bob_before = get_account_balance::<E>(acc.bob).unwrap();
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(60);
+            contract.join_project_presale(project_id, 10, None, None);
+
+            assert_eq!(get_account_balance::<E>(acc.bob).unwrap(), bob_before + 10);
+            assert_eq!(contract.get_contribution(project_id, acc.bob).paid_amount, 50);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "Insufficient payment")]
+        fn underpayment_is_rejected() {
+            let acc = default_accounts::<E>();
+            let mut contract = contract();
+            let project_id = add_project(&mut contract, acc.alice);
+            advance_to(10);
+
+            set_caller::<E>(acc.bob);
+            set_value_transferred::<E>(49);
+            contract.join_project_presale(project_id, 10, None, None);
+        }
+    }
+
+    #[cfg(all(test, feature = "e2e-tests"))]
+    mod e2e_tests {
+        use super::*;
+        use ink_e2e::ContractsBackend;
+        use malicious_token::{MaliciousToken, MaliciousTokenRef};
+        type E2EResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+        /// A staking token re-entering `stake` from inside `transfer_from` must be
+        /// rejected by the guard. The reentrant stake is paid for by the token, so
+        /// without the guard it would go through and double the stake.
+        #[ink_e2e::test]
+        async fn reentrant_token_cannot_stake_twice<Client: E2EBackend>(
+            mut client: Client,
+        ) -> E2EResult<()> {
+            let mut token_constructor = MaliciousTokenRef::new(1_000);
+            let token = client
+                .instantiate("malicious-token", &ink_e2e::alice(), &mut token_constructor)
+                .submit()
+                .await
+                .expect("malicious-token instantiate failed");
+            let mut token_call = token.call_builder::<MaliciousToken>();
+
+            let alice = ink_e2e::account_id(ink_e2e::AccountKeyring::Alice);
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
+            let mut presale_constructor = PolkadotPresaleContractRef::new(alice, alice);
+            let presale = client
+                .instantiate("polkadot_presale_contract", &ink_e2e::alice(), &mut presale_constructor)
+                .submit()
+                .await
+                .expect("polkadot_presale_contract instantiate failed");
+            let mut presale_call = presale.call_builder::<PolkadotPresaleContract>();
+
+            let set_staking_token = presale_call.set_staking_token(token.account_id);
+            client
+                .call(&ink_e2e::alice(), &set_staking_token)
+                .submit()
+                .await
+                .expect("Calling `set_staking_token` failed");
+
+            // The token stakes its own balance, so it is the caller of the reentrant `stake`.
+            let fund = token_call.transfer(token.account_id, 300, Vec::new());
+            client
+                .call(&ink_e2e::alice(), &fund)
+                .submit()
+                .await
+                .expect("Calling `transfer` failed");
+
+            let arm_approve = token_call.set_reentry(
+                token.account_id,
+                ink::selector_bytes!("PSP22::approve"),
+                ink::scale::Encode::encode(&(presale.account_id, 300u128)),
+            );
+            let arm_stake = token_call.set_reentry(
+                presale.account_id,
+                ink::selector_bytes!("stake"),
+                ink::scale::Encode::encode(&100u128),
+            );
+            // The first transfer approves the presale, the second stakes through
+            // the presale, whose `transfer_from` then tries to stake again.
+            for arm in [&arm_approve, &arm_stake, &arm_stake] {
+                client
+                    .call(&ink_e2e::alice(), arm)
+                    .submit()
+                    .await
+                    .expect("Calling `set_reentry` failed");
+            }
+            let trigger = token_call.transfer(bob, 1, Vec::new());
+            for _ in 0..2 {
+                client
+                    .call(&ink_e2e::alice(), &trigger)
+                    .submit()
+                    .await
+                    .expect("Calling `transfer` failed");
+            }
+
+            let get_stake = presale_call.get_stake(token.account_id);
+            let stake = client
+                .call(&ink_e2e::alice(), &get_stake)
+                .dry_run()
+                .await?
+                .return_value();
+            assert_eq!(stake.amount, 100);
+
+            let balance_of = token_call.balance_of(presale.account_id);
+            let staked = client
+                .call(&ink_e2e::alice(), &balance_of)
+                .dry_run()
+                .await?
+                .return_value();
+            assert_eq!(staked, 100);
+
+            Ok(())
         }
     }
 }
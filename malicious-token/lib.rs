@@ -0,0 +1,161 @@
+#![cfg_attr(not(feature = "std"), no_std, no_main)]
+pub use self::malicious_token::{MaliciousToken, MaliciousTokenRef};
+
+// A PSP22 token used only by the E2E tests. Every `set_reentry` queues a
+// callback, and each following `transfer`/`transfer_from` first makes the next
+// queued call into `target` with the given selector and SCALE-encoded arguments,
+// allowing reentry on its side. The outcome of the callback is ignored so that
+// tests can assert on the resulting state instead.
+#[ink::contract]
+mod malicious_token {
+    use ink::env::call::{build_call, ExecutionInput, Selector};
+    use ink::env::CallFlags;
+    use ink::prelude::vec::Vec;
+    use ink::storage::Lazy;
+    use token_contract::{PSP22Data, PSP22Error, PSP22Event, PSP22};
+
+    // Callee, selector and SCALE-encoded arguments of the reentrant call.
+    type Reentry = (AccountId, [u8; 4], Vec<u8>);
+
+    // Call arguments which are already SCALE-encoded.
+    struct RawInput<'a>(&'a [u8]);
+
+    impl ink::scale::Encode for RawInput<'_> {
+        fn size_hint(&self) -> usize {
+            self.0.len()
+        }
+
+        fn encode_to<T: ink::scale::Output + ?Sized>(&self, dest: &mut T) {
+            dest.write(self.0);
+        }
+    }
+
+    #[ink(storage)]
+    pub struct MaliciousToken {
+        data: PSP22Data,
+        // Kept in a `Lazy` cell so that taking a callback is visible to nested calls.
+        reentries: Lazy<Vec<Reentry>>,
+    }
+
+    impl MaliciousToken {
+        #[ink(constructor)]
+        pub fn new(supply: u128) -> Self {
+            let (data, _events) = PSP22Data::new(supply, Self::env().caller());
+            Self {
+                data,
+                reentries: Lazy::default(),
+            }
+        }
+
+        #[ink(message)]
+        pub fn set_reentry(&mut self, target: AccountId, selector: [u8; 4], input: Vec<u8>) {
+            let mut reentries = self.reentries.get_or_default();
+            reentries.push((target, selector, input));
+            self.reentries.set(&reentries);
+        }
+
+        fn reenter(&mut self) {
+            let mut reentries = self.reentries.get_or_default();
+            if !reentries.is_empty() {
+                let (target, selector, input) = reentries.remove(0);
+                self.reentries.set(&reentries);
+                let _ = build_call::<Environment>()
+                    .call(target)
+                    .call_flags(CallFlags::ALLOW_REENTRY)
+                    .exec_input(
+                        ExecutionInput::new(Selector::new(selector)).push_arg(RawInput(&input)),
+                    )
+                    .returns::<()>()
+                    .try_invoke();
+            }
+        }
+
+        fn emit_events(&self, events: Vec<PSP22Event>) {
+            for event in events {
+                match event {
+                    PSP22Event::Transfer(e) => self.env().emit_event(e),
+                    PSP22Event::Approval(e) => self.env().emit_event(e),
+                }
+            }
+        }
+    }
+
+    impl PSP22 for MaliciousToken {
+        #[ink(message)]
+        fn total_supply(&self) -> u128 {
+            self.data.total_supply()
+        }
+
+        #[ink(message)]
+        fn balance_of(&self, owner: AccountId) -> u128 {
+            self.data.balance_of(owner)
+        }
+
+        #[ink(message)]
+        fn allowance(&self, owner: AccountId, spender: AccountId) -> u128 {
+            self.data.allowance(owner, spender)
+        }
+
+        #[ink(message)]
+        fn transfer(
+            &mut self,
+            to: AccountId,
+            value: u128,
+            _data: Vec<u8>,
+        ) -> Result<(), PSP22Error> {
+            self.reenter();
+            let events = self.data.transfer(self.env().caller(), to, value)?;
+            self.emit_events(events);
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn transfer_from(
+            &mut self,
+            from: AccountId,
+            to: AccountId,
+            value: u128,
+            _data: Vec<u8>,
+        ) -> Result<(), PSP22Error> {
+            self.reenter();
+            let events = self
+                .data
+                .transfer_from(self.env().caller(), from, to, value)?;
+            self.emit_events(events);
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn approve(&mut self, spender: AccountId, value: u128) -> Result<(), PSP22Error> {
+            let events = self.data.approve(self.env().caller(), spender, value)?;
+            self.emit_events(events);
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn increase_allowance(
+            &mut self,
+            spender: AccountId,
+            delta_value: u128,
+        ) -> Result<(), PSP22Error> {
+            let events = self
+                .data
+                .increase_allowance(self.env().caller(), spender, delta_value)?;
+            self.emit_events(events);
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn decrease_allowance(
+            &mut self,
+            spender: AccountId,
+            delta_value: u128,
+        ) -> Result<(), PSP22Error> {
+            let events = self
+                .data
+                .decrease_allowance(self.env().caller(), spender, delta_value)?;
+            self.emit_events(events);
+            Ok(())
+        }
+    }
+}
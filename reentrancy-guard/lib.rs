@@ -0,0 +1,71 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use ink::storage::Lazy;
+
+#[derive(Debug, PartialEq, Eq)]
+#[ink::scale_derive(Encode, Decode, TypeInfo)]
+pub enum ReentrancyError {
+    /// Returned when a guarded message is entered while another guarded
+    /// message of the same contract is still executing.
+    ReentrantCall,
+}
+
+/// A non-reentrant lock shared by the presale and lock contracts.
+///
+/// Messages that call into other contracts (PSP22 transfers, the token
+/// factory) or pay out native value wrap their body in `enter`/`exit`.
+///
+/// The flag is kept in its own `Lazy` storage cell so that `enter` writes it
+/// through to contract storage immediately. A plain field of the contract
+/// struct is only flushed when the outer message returns, so a re-entered
+/// instance would still read the stale `false`.
+#[ink::storage_item]
+#[derive(Debug, Default)]
+pub struct ReentrancyGuard {
+    entered: Lazy<bool>,
+}
+
+impl ReentrancyGuard {
+    /// Marks the contract as entered.
+    ///
+    /// Fails with `ReentrantCall` if the contract is already entered.
+    pub fn enter(&mut self) -> Result<(), ReentrancyError> {
+        if self.is_entered() {
+            return Err(ReentrancyError::ReentrantCall);
+        }
+        self.entered.set(&true);
+        Ok(())
+    }
+
+    /// Releases the lock taken by `enter`.
+    pub fn exit(&mut self) {
+        self.entered.set(&false);
+    }
+
+    pub fn is_entered(&self) -> bool {
+        self.entered.get().unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[ink::test]
+    fn enter_and_exit_works() {
+        let mut guard = ReentrancyGuard::default();
+        assert!(!guard.is_entered());
+        assert_eq!(guard.enter(), Ok(()));
+        assert!(guard.is_entered());
+        guard.exit();
+        assert!(!guard.is_entered());
+        assert_eq!(guard.enter(), Ok(()));
+    }
+
+    #[ink::test]
+    fn second_enter_fails() {
+        let mut guard = ReentrancyGuard::default();
+        assert_eq!(guard.enter(), Ok(()));
+        assert_eq!(guard.enter(), Err(ReentrancyError::ReentrantCall));
+    }
+}
@@ -24,7 +24,7 @@ pub use self::token_contract::TokenContractRef;
 // and include unit tests (6).
 #[ink::contract]
 mod token_contract {
-    use crate::{PSP22Data, PSP22Error, PSP22Event, PSP22Metadata, PSP22};
+    use crate::{PSP22Burnable, PSP22Data, PSP22Error, PSP22Event, PSP22Metadata, PSP22};
     use ink::prelude::{string::String, vec::Vec};
 
     #[ink(storage)]
@@ -168,6 +168,15 @@ mod token_contract {
         }
     }
 
+    impl PSP22Burnable for TokenContract {
+        #[ink(message)]
+        fn burn(&mut self, value: u128) -> Result<(), PSP22Error> {
+            let events = self.data.burn(self.env().caller(), value)?;
+            self.emit_events(events);
+            Ok(())
+        }
+    }
+
     // (6)
     #[cfg(test)]
     mod tests {
@@ -1,44 +1,255 @@
 #![cfg_attr(not(feature = "std"), no_std, no_main)]
+
+mod psp34;
+
+pub use psp34::{Id, PSP34Error, PSP34Metadata, PSP34};
 pub use self::token_lock::TokenLockRef;
 
 #[ink::contract]
 mod token_lock {
+    use crate::{Id, PSP34Error, PSP34Metadata, PSP34};
     use token_contract::TokenContractRef;
-    use token_contract::PSP22;
+    use ink::codegen::TraitCallBuilder;
+    use ink::env::CallFlags;
+    use token_contract::{PSP22Error, PSP22};
+    use reentrancy_guard::ReentrancyGuard;
     use ink::storage::Mapping as StorageHashMap;
     use ink::prelude::{
         vec::Vec,
     };
 
 
+    /// Linear vesting of a lock between `start_time` and `start_time + duration_time`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout)
+    )]
+    pub struct Vesting {
+        /// Time after `start_time` before anything vests.
+        cliff: Timestamp,
+        /// If set, tokens vest in steps of this length instead of continuously.
+        slice_period: Option<Timestamp>,
+    }
+
+    /// Part of a lock unlocked by a milestone, either a percentage of the locked amount or a fixed amount.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    pub enum MilestoneShare {
+        Percentage(u8),
+        Amount(Balance),
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    pub struct Milestone {
+        pub unlock_time: Timestamp,
+        pub share: MilestoneShare,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout)
+    )]
+    pub enum Schedule {
+        Linear(Vesting),
+        /// Unlock times in ascending order with the amount each of them unlocks.
+        Milestones(Vec<(Timestamp, Balance)>),
+    }
+
+    /// Upper bound for the number of milestones of a lock.
+    const MAX_MILESTONES: usize = 50;
+    /// Upper bound for the number of locks returned by one paged query.
+    const MAX_PAGE_SIZE: u32 = 100;
+
+    #[derive(Clone)]
     #[ink::scale_derive(Encode, Decode, TypeInfo)]
     #[cfg_attr(
         feature = "std",
         derive(ink::storage::traits::StorageLayout)
     )]
     pub struct TimeLockDetails {
-        token_address: AccountId,
+        /// `None` for locks of the native currency.
+        token_address: Option<AccountId>,
         token_owner: AccountId,
+        /// Receiver of released tokens, the owner unless changed.
+        beneficiary: AccountId,
+        /// Account proposed by the owner to take over the lock.
+        pending_owner: Option<AccountId>,
         locked_amount: Balance,
         start_time: Timestamp,
         duration_time: Timestamp,
+        /// `None` for locks that unlock all at once after `duration_time`.
+        schedule: Option<Schedule>,
+        released_amount: Balance,
+        released: bool,
+    }
+
+    /// Read-only view of a lock returned by the query messages.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    pub struct LockView {
+        pub lock_id: u32,
+        /// `None` for locks of the native currency.
+        pub token_address: Option<AccountId>,
+        pub owner: AccountId,
+        pub beneficiary: AccountId,
+        pub pending_owner: Option<AccountId>,
+        pub locked_amount: Balance,
+        pub released_amount: Balance,
+        pub releasable_amount: Balance,
+        pub start_time: Timestamp,
+        /// Time at which everything is unlocked.
+        pub unlock_time: Timestamp,
+        pub schedule: Option<Schedule>,
+        pub released: bool,
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    #[ink::scale_derive(Encode, Decode, TypeInfo)]
+    pub enum LockError {
+        NotFound,
+        /// Nothing of the lock is unlocked yet.
+        NotExpired,
+        Unauthorized,
+        AlreadyReleased,
+        TokenError(PSP22Error),
+        /// Invalid vesting, milestones, or a lock shortened.
+        InvalidSchedule,
+        InvalidAmount,
+        /// The operation isn't available for this kind of lock.
+        UnsupportedLock,
+        NativeTransferFailed,
+        ReentrantCall,
+    }
+
+    impl From<PSP22Error> for LockError {
+        fn from(error: PSP22Error) -> Self {
+            LockError::TokenError(error)
+        }
+    }
+
+    /// PSP34 transfer of a lock receipt. `from` is `None` on mint, `to` is `None` on burn.
+    #[ink(event)]
+    pub struct Transfer {
+        #[ink(topic)]
+        from: Option<AccountId>,
+        #[ink(topic)]
+        to: Option<AccountId>,
+        #[ink(topic)]
+        id: Id,
+    }
+
+    #[ink(event)]
+    pub struct Approval {
+        #[ink(topic)]
+        owner: AccountId,
+        #[ink(topic)]
+        operator: AccountId,
+        #[ink(topic)]
+        id: Option<Id>,
+        approved: bool,
+    }
+
+    #[ink(event)]
+    pub struct LockCreated {
+        #[ink(topic)]
+        lock_id: u32,
+        #[ink(topic)]
+        token_address: Option<AccountId>,
+        owner: AccountId,
+        amount: Balance,
+        unlock_time: Timestamp,
+    }
+
+    #[ink(event)]
+    pub struct LockReleased {
+        #[ink(topic)]
+        lock_id: u32,
+        beneficiary: AccountId,
+        amount: Balance,
+    }
+
+    #[ink(event)]
+    pub struct LockTransferred {
+        #[ink(topic)]
+        lock_id: u32,
+        from: AccountId,
+        to: AccountId,
+    }
+
+    #[ink(event)]
+    pub struct LockExtended {
+        #[ink(topic)]
+        lock_id: u32,
+        previous_duration_time: Timestamp,
+        duration_time: Timestamp,
+    }
+
+    #[ink(event)]
+    pub struct LockToppedUp {
+        #[ink(topic)]
+        lock_id: u32,
+        amount: Balance,
+        locked_amount: Balance,
+    }
+
+    #[ink(event)]
+    pub struct LockSplit {
+        #[ink(topic)]
+        lock_id: u32,
+        #[ink(topic)]
+        new_lock_id: u32,
+        amount: Balance,
+        beneficiary: AccountId,
     }
-    
+
     #[ink(storage)]
     pub struct TokenLock {
-        tokens: StorageHashMap<AccountId, TokenContractRef>,
-        token_lock_details: StorageHashMap<AccountId, TimeLockDetails>
+        token_lock_details: StorageHashMap<u32, TimeLockDetails>,
+        last_lock_id: u32,
+        // Ids of all locks of a token and of an owner, keyed by their position in the
+        // list, next to the length of each list.
+        locks_by_token: StorageHashMap<(AccountId, u32), u32>,
+        token_lock_counts: StorageHashMap<AccountId, u32>,
+        locks_by_owner: StorageHashMap<(AccountId, u32), u32>,
+        owner_lock_counts: StorageHashMap<AccountId, u32>,
+        // Position of each lock in the list of its owner.
+        owner_lock_positions: StorageHashMap<u32, u32>,
+        guard: ReentrancyGuard,
+        // Every unreleased lock is a PSP34 token with the lock id as its id, held by the lock owner.
+        receipt_balances: StorageHashMap<AccountId, u32>,
+        receipt_approvals: StorageHashMap<u32, AccountId>,
+        // Operators approved for all receipts of an owner.
+        receipt_operators: StorageHashMap<(AccountId, AccountId), ()>,
+        receipt_supply: u128,
     }
 
     impl TokenLock {
         #[ink(constructor)]
         pub fn new() -> Self {
             Self {
-                tokens: StorageHashMap::default(),
                 token_lock_details: StorageHashMap::default(),
+                last_lock_id: 0,
+                locks_by_token: StorageHashMap::default(),
+                token_lock_counts: StorageHashMap::default(),
+                locks_by_owner: StorageHashMap::default(),
+                owner_lock_counts: StorageHashMap::default(),
+                owner_lock_positions: StorageHashMap::default(),
+                guard: ReentrancyGuard::default(),
+                receipt_balances: StorageHashMap::default(),
+                receipt_approvals: StorageHashMap::default(),
+                receipt_operators: StorageHashMap::default(),
+                receipt_supply: 0,
             }
         }
 
+        /// Locks `lock_amount` of the token, pulled from the caller, and returns the id of the new lock.
+        ///
+        /// The caller must have approved this contract for `lock_amount`.
         #[ink(message)]
         pub fn create_lock(
             &mut self,
@@ -46,35 +257,665 @@ mod token_lock {
             token_owner: AccountId,
             lock_amount: Balance,
             duration_time: Timestamp,
-        ) -> AccountId {
+        ) -> Result<u32, LockError> {
+            if lock_amount == 0 {
+                return Err(LockError::InvalidAmount);
+            }
+
             let token_details = TimeLockDetails {
-                token_address,
+                token_address: Some(token_address),
                 token_owner,
+                beneficiary: token_owner,
+                pending_owner: None,
                 locked_amount: lock_amount,
                 start_time: self.time_now(),
-                duration_time
+                duration_time,
+                schedule: None,
+                released_amount: 0,
+                released: false,
             };
-            self.token_lock_details.insert(token_address, &token_details);
-            
-            let _data = Vec::new();
-            let mut token_contract: TokenContractRef = ink::env::call::FromAccountId::from_account_id(token_address);
-            let _= token_contract.transfer_from(self.env().caller(), self.env().account_id(), lock_amount, _data);
+            self.non_reentrant(|contract| contract.insert_lock(token_address, token_details))
+        }
 
-            token_address
+        /// Locks `lock_amount` of the token, vesting linearly from `start_time` over
+        /// `duration_time`, and returns the id of the new lock.
+        ///
+        /// Nothing vests before `start_time + cliff`. With a `slice_period` the vested
+        /// amount only grows at whole multiples of it.
+        #[ink(message)]
+        #[allow(clippy::too_many_arguments)]
+        pub fn create_vesting_lock(
+            &mut self,
+            token_address: AccountId,
+            token_owner: AccountId,
+            lock_amount: Balance,
+            start_time: Timestamp,
+            cliff: Timestamp,
+            duration_time: Timestamp,
+            slice_period: Option<Timestamp>,
+        ) -> Result<u32, LockError> {
+            if lock_amount == 0 {
+                return Err(LockError::InvalidAmount);
+            }
+
+            let token_details = TimeLockDetails {
+                token_address: Some(token_address),
+                token_owner,
+                beneficiary: token_owner,
+                pending_owner: None,
+                locked_amount: lock_amount,
+                start_time,
+                duration_time,
+                schedule: Some(Self::linear_schedule(cliff, duration_time, slice_period)?),
+                released_amount: 0,
+                released: false,
+            };
+            self.non_reentrant(|contract| contract.insert_lock(token_address, token_details))
         }
 
+        /// Locks `lock_amount` of the token, unlocking it in steps at the given milestones,
+        /// and returns the id of the new lock.
+        ///
+        /// Milestones must be in ascending order and add up to exactly `lock_amount`.
         #[ink(message)]
-        pub fn release_lock(
+        pub fn create_milestone_lock(
             &mut self,
             token_address: AccountId,
-        ) -> AccountId {
-            let token_details = self.token_lock_details.get(token_address).unwrap();
-            assert!(token_details.start_time.checked_add(token_details.duration_time) > Some(self.time_now()), "Lock duration isn't expired");
+            token_owner: AccountId,
+            lock_amount: Balance,
+            milestones: Vec<Milestone>,
+        ) -> Result<u32, LockError> {
+            if lock_amount == 0 {
+                return Err(LockError::InvalidAmount);
+            }
+
+            let steps = Self::milestone_steps(lock_amount, &milestones)?;
+            let start_time = self.time_now();
+            let last_unlock = steps.last().map(|(unlock_time, _)| *unlock_time).unwrap_or_default();
+            let token_details = TimeLockDetails {
+                token_address: Some(token_address),
+                token_owner,
+                beneficiary: token_owner,
+                pending_owner: None,
+                locked_amount: lock_amount,
+                start_time,
+                duration_time: last_unlock.saturating_sub(start_time),
+                schedule: Some(Schedule::Milestones(steps)),
+                released_amount: 0,
+                released: false,
+            };
+            self.non_reentrant(|contract| contract.insert_lock(token_address, token_details))
+        }
+
+        /// Locks the transferred native value for `token_owner` and returns the id of the new lock.
+        #[ink(message, payable)]
+        pub fn create_native_lock(
+            &mut self,
+            token_owner: AccountId,
+            duration_time: Timestamp,
+        ) -> Result<u32, LockError> {
+            let lock_amount = self.env().transferred_value();
+            if lock_amount == 0 {
+                return Err(LockError::InvalidAmount);
+            }
+
+            let token_details = TimeLockDetails {
+                token_address: None,
+                token_owner,
+                beneficiary: token_owner,
+                pending_owner: None,
+                locked_amount: lock_amount,
+                start_time: self.time_now(),
+                duration_time,
+                schedule: None,
+                released_amount: 0,
+                released: false,
+            };
+            Ok(self.store_lock(&token_details))
+        }
+
+        /// Locks the transferred native value with the same vesting as `create_vesting_lock`
+        /// and returns the id of the new lock.
+        #[ink(message, payable)]
+        pub fn create_native_vesting_lock(
+            &mut self,
+            token_owner: AccountId,
+            start_time: Timestamp,
+            cliff: Timestamp,
+            duration_time: Timestamp,
+            slice_period: Option<Timestamp>,
+        ) -> Result<u32, LockError> {
+            let lock_amount = self.env().transferred_value();
+            if lock_amount == 0 {
+                return Err(LockError::InvalidAmount);
+            }
+
+            let token_details = TimeLockDetails {
+                token_address: None,
+                token_owner,
+                beneficiary: token_owner,
+                pending_owner: None,
+                locked_amount: lock_amount,
+                start_time,
+                duration_time,
+                schedule: Some(Self::linear_schedule(cliff, duration_time, slice_period)?),
+                released_amount: 0,
+                released: false,
+            };
+            Ok(self.store_lock(&token_details))
+        }
+
+        fn linear_schedule(
+            cliff: Timestamp,
+            duration_time: Timestamp,
+            slice_period: Option<Timestamp>,
+        ) -> Result<Schedule, LockError> {
+            let valid_slice = slice_period.is_none_or(|slice_period| slice_period > 0 && slice_period <= duration_time);
+            if cliff > duration_time || !valid_slice {
+                return Err(LockError::InvalidSchedule);
+            }
+
+            Ok(Schedule::Linear(Vesting { cliff, slice_period }))
+        }
+
+        // Turns milestones into the amounts they unlock, which must add up to `lock_amount`.
+        fn milestone_steps(lock_amount: Balance, milestones: &[Milestone]) -> Result<Vec<(Timestamp, Balance)>, LockError> {
+            if milestones.is_empty()
+                || milestones.len() > MAX_MILESTONES
+                || !milestones.windows(2).all(|pair| pair[0].unlock_time < pair[1].unlock_time)
+            {
+                return Err(LockError::InvalidSchedule);
+            }
+
+            let mut steps: Vec<(Timestamp, Balance)> = Vec::with_capacity(milestones.len());
+            // Sum of what flooring each percentage share cut off, in hundredths.
+            let mut rounded_off: Balance = 0;
+            for milestone in milestones {
+                let amount = match milestone.share {
+                    MilestoneShare::Percentage(percentage) if percentage <= 100 => {
+                        let share = lock_amount.checked_mul(Balance::from(percentage)).ok_or(LockError::InvalidAmount)?;
+                        rounded_off += share % 100;
+                        share / 100
+                    }
+                    MilestoneShare::Percentage(_) => return Err(LockError::InvalidSchedule),
+                    MilestoneShare::Amount(amount) => amount,
+                };
+                steps.push((milestone.unlock_time, amount));
+            }
+            // The last milestone unlocks whatever the rounding left over.
+            if let Some((_, amount)) = steps.last_mut() {
+                *amount = amount.checked_add(rounded_off / 100).ok_or(LockError::InvalidAmount)?;
+            }
+
+            let total = steps
+                .iter()
+                .try_fold(0, |total: Balance, (_, amount)| total.checked_add(*amount))
+                .ok_or(LockError::InvalidAmount)?;
+            if total != lock_amount {
+                return Err(LockError::InvalidAmount);
+            }
+            Ok(steps)
+        }
+
+        // Runs `f` behind the reentrancy guard.
+        //
+        // Tokens are called with `ALLOW_REENTRY` so that tokens with transfer hooks
+        // can still call back into this contract, which leaves rejecting reentrant
+        // calls into the messages moving tokens to the guard.
+        fn non_reentrant<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, LockError>) -> Result<T, LockError> {
+            self.guard.enter().map_err(|_| LockError::ReentrantCall)?;
+            let result = f(self);
+            self.guard.exit();
+            result
+        }
+
+        // Stores a new token lock and pulls its tokens from the caller.
+        fn insert_lock(&mut self, token_address: AccountId, token_details: TimeLockDetails) -> Result<u32, LockError> {
+            let lock_id = self.store_lock(&token_details);
 
-            let _data = Vec::new();
             let mut token_contract: TokenContractRef = ink::env::call::FromAccountId::from_account_id(token_address);
-            let _ = token_contract.transfer_from(self.env().account_id(), token_details.token_owner, token_details.locked_amount, _data);
-            token_address
+            token_contract
+                .call_mut()
+                .transfer_from(self.env().caller(), self.env().account_id(), token_details.locked_amount, Vec::new())
+                .call_flags(CallFlags::ALLOW_REENTRY)
+                .invoke()?;
+            Ok(lock_id)
+        }
+
+        // Stores a new lock under the next lock id and indexes it.
+        fn store_lock(&mut self, token_details: &TimeLockDetails) -> u32 {
+            let lock_id = self.last_lock_id.checked_add(1).expect("Overflow detected in lock_id calculation");
+            self.last_lock_id = lock_id;
+            self.token_lock_details.insert(lock_id, token_details);
+
+            if let Some(token_address) = token_details.token_address {
+                self.push_token_lock(token_address, lock_id);
+            }
+            self.push_owner_lock(token_details.token_owner, lock_id);
+
+            // Mint the receipt.
+            let balance = self.receipt_balances.get(token_details.token_owner).unwrap_or(0);
+            self.receipt_balances.insert(token_details.token_owner, &(balance + 1));
+            self.receipt_supply += 1;
+            self.env().emit_event(Transfer { from: None, to: Some(token_details.token_owner), id: Id::U32(lock_id) });
+
+            self.env().emit_event(LockCreated {
+                lock_id,
+                token_address: token_details.token_address,
+                owner: token_details.token_owner,
+                amount: token_details.locked_amount,
+                unlock_time: token_details.start_time.saturating_add(token_details.duration_time),
+            });
+            lock_id
+        }
+
+        // Appends `lock_id` to the locks of `token_address`.
+        fn push_token_lock(&mut self, token_address: AccountId, lock_id: u32) {
+            let count = self.token_lock_counts.get(token_address).unwrap_or(0);
+            self.locks_by_token.insert((token_address, count), &lock_id);
+            self.token_lock_counts.insert(token_address, &count.checked_add(1).expect("Invalid Operation"));
+        }
+
+        // Appends `lock_id` to the locks of `owner` and remembers its position.
+        fn push_owner_lock(&mut self, owner: AccountId, lock_id: u32) {
+            let count = self.owner_lock_counts.get(owner).unwrap_or(0);
+            self.locks_by_owner.insert((owner, count), &lock_id);
+            self.owner_lock_positions.insert(lock_id, &count);
+            self.owner_lock_counts.insert(owner, &count.checked_add(1).expect("Invalid Operation"));
+        }
+
+        /// Pushes the end of a lock further out. Locks can never be shortened.
+        #[ink(message)]
+        pub fn extend_lock(&mut self, lock_id: u32, duration_time: Timestamp) -> Result<(), LockError> {
+            let mut token_details = self.changeable_lock(lock_id)?;
+            if duration_time < token_details.duration_time {
+                return Err(LockError::InvalidSchedule);
+            }
+
+            let previous_duration_time = token_details.duration_time;
+            token_details.duration_time = duration_time;
+            self.token_lock_details.insert(lock_id, &token_details);
+
+            self.env().emit_event(LockExtended { lock_id, previous_duration_time, duration_time });
+            Ok(())
+        }
+
+        /// Adds `amount` tokens, pulled from the caller, to an existing lock.
+        #[ink(message)]
+        pub fn top_up_lock(&mut self, lock_id: u32, amount: Balance) -> Result<(), LockError> {
+            self.non_reentrant(|contract| {
+                let mut token_details = contract.changeable_lock(lock_id)?;
+                // Native value can't be pulled from the caller.
+                let token_address = token_details.token_address.ok_or(LockError::UnsupportedLock)?;
+                token_details.locked_amount = token_details.locked_amount.checked_add(amount).ok_or(LockError::InvalidAmount)?;
+                contract.token_lock_details.insert(lock_id, &token_details);
+
+                let mut token_contract: TokenContractRef = ink::env::call::FromAccountId::from_account_id(token_address);
+                token_contract
+                    .call_mut()
+                    .transfer_from(contract.env().caller(), contract.env().account_id(), amount, Vec::new())
+                    .call_flags(CallFlags::ALLOW_REENTRY)
+                    .invoke()?;
+
+                contract.env().emit_event(LockToppedUp { lock_id, amount, locked_amount: token_details.locked_amount });
+                Ok(())
+            })
+        }
+
+        /// Moves `amount` of the unreleased tokens of a lock into a new lock with the same
+        /// owner and schedule releasing to `beneficiary`, and returns the id of the new lock.
+        #[ink(message)]
+        pub fn split_lock(&mut self, lock_id: u32, amount: Balance, beneficiary: AccountId) -> Result<u32, LockError> {
+            let mut token_details = self.changeable_lock(lock_id)?;
+            if amount == 0 || amount >= token_details.locked_amount - token_details.released_amount {
+                return Err(LockError::InvalidAmount);
+            }
+
+            // The new lock takes over its share of what was already released, rounded up,
+            // so that splitting never lets anything vest ahead of the schedule.
+            let released_share = token_details
+                .released_amount
+                .checked_mul(amount)
+                .ok_or(LockError::InvalidAmount)?
+                .div_ceil(token_details.locked_amount);
+            token_details.locked_amount -= amount;
+            token_details.released_amount -= released_share;
+            self.token_lock_details.insert(lock_id, &token_details);
+
+            let new_lock = TimeLockDetails {
+                beneficiary,
+                pending_owner: None,
+                locked_amount: amount,
+                released_amount: released_share,
+                ..token_details
+            };
+            let new_lock_id = self.store_lock(&new_lock);
+
+            self.env().emit_event(LockSplit { lock_id, new_lock_id, amount, beneficiary });
+            Ok(new_lock_id)
+        }
+
+        /// Proposes `new_owner` as the owner of a lock. The change only takes effect
+        /// once `new_owner` accepts it with `accept_lock_ownership`.
+        #[ink(message)]
+        pub fn transfer_lock_ownership(&mut self, lock_id: u32, new_owner: AccountId) -> Result<(), LockError> {
+            let mut token_details = self.token_lock_details.get(lock_id).ok_or(LockError::NotFound)?;
+            if self.env().caller() != token_details.token_owner {
+                return Err(LockError::Unauthorized);
+            }
+
+            token_details.pending_owner = Some(new_owner);
+            self.token_lock_details.insert(lock_id, &token_details);
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn accept_lock_ownership(&mut self, lock_id: u32) -> Result<(), LockError> {
+            let token_details = self.token_lock_details.get(lock_id).ok_or(LockError::NotFound)?;
+            let new_owner = self.env().caller();
+            if token_details.pending_owner != Some(new_owner) {
+                return Err(LockError::Unauthorized);
+            }
+
+            self.move_lock(lock_id, token_details, new_owner);
+            Ok(())
+        }
+
+        // Hands a lock and its receipt over to `new_owner`.
+        fn move_lock(&mut self, lock_id: u32, mut token_details: TimeLockDetails, new_owner: AccountId) {
+            let previous_owner = token_details.token_owner;
+            // Swap the last lock of the previous owner into the freed position.
+            let position = self.owner_lock_positions.get(lock_id).unwrap_or_default();
+            let last = self.owner_lock_counts.get(previous_owner).unwrap_or(1) - 1;
+            if position != last {
+                let moved = self.locks_by_owner.get((previous_owner, last)).expect("Lock index corrupted");
+                self.locks_by_owner.insert((previous_owner, position), &moved);
+                self.owner_lock_positions.insert(moved, &position);
+            }
+            self.locks_by_owner.remove((previous_owner, last));
+            self.owner_lock_counts.insert(previous_owner, &last);
+            self.push_owner_lock(new_owner, lock_id);
+
+            token_details.token_owner = new_owner;
+            token_details.pending_owner = None;
+            self.token_lock_details.insert(lock_id, &token_details);
+
+            if !token_details.released {
+                let balance = self.receipt_balances.get(previous_owner).unwrap_or(0);
+                self.receipt_balances.insert(previous_owner, &balance.saturating_sub(1));
+                let balance = self.receipt_balances.get(new_owner).unwrap_or(0);
+                self.receipt_balances.insert(new_owner, &(balance + 1));
+                self.receipt_approvals.remove(lock_id);
+                self.env().emit_event(Transfer { from: Some(previous_owner), to: Some(new_owner), id: Id::U32(lock_id) });
+            }
+            self.env().emit_event(LockTransferred { lock_id, from: previous_owner, to: new_owner });
+        }
+
+        // Holder of the receipt of a lock, `None` once the lock is fully released.
+        fn receipt_owner(&self, id: &Id) -> Option<AccountId> {
+            let Id::U32(lock_id) = id else { return None };
+            self.token_lock_details
+                .get(lock_id)
+                .filter(|token_details| !token_details.released)
+                .map(|token_details| token_details.token_owner)
+        }
+
+        /// Changes the account that receives the released tokens of a lock.
+        #[ink(message)]
+        pub fn set_beneficiary(&mut self, lock_id: u32, beneficiary: AccountId) -> Result<(), LockError> {
+            let mut token_details = self.token_lock_details.get(lock_id).ok_or(LockError::NotFound)?;
+            if self.env().caller() != token_details.token_owner {
+                return Err(LockError::Unauthorized);
+            }
+
+            token_details.beneficiary = beneficiary;
+            self.token_lock_details.insert(lock_id, &token_details);
+            Ok(())
+        }
+
+        // Loads a lock the caller owns and that can still be changed.
+        fn changeable_lock(&self, lock_id: u32) -> Result<TimeLockDetails, LockError> {
+            let token_details = self.token_lock_details.get(lock_id).ok_or(LockError::NotFound)?;
+            if self.env().caller() != token_details.token_owner {
+                return Err(LockError::Unauthorized);
+            }
+            if token_details.released {
+                return Err(LockError::AlreadyReleased);
+            }
+            // Milestone amounts are fixed to add up to the locked amount.
+            if matches!(token_details.schedule, Some(Schedule::Milestones(_))) {
+                return Err(LockError::UnsupportedLock);
+            }
+            Ok(token_details)
+        }
+
+        // Loads an unreleased lock the caller owns or is the beneficiary of.
+        fn releasable_lock(&self, lock_id: u32) -> Result<TimeLockDetails, LockError> {
+            let token_details = self.token_lock_details.get(lock_id).ok_or(LockError::NotFound)?;
+            if ![token_details.token_owner, token_details.beneficiary].contains(&self.env().caller()) {
+                return Err(LockError::Unauthorized);
+            }
+            if token_details.released {
+                return Err(LockError::AlreadyReleased);
+            }
+            Ok(token_details)
+        }
+
+        /// Sends all remaining locked tokens to the beneficiary once the lock duration has passed.
+        ///
+        /// Only the owner or the beneficiary can release a lock, and only once.
+        #[ink(message)]
+        pub fn release_lock(
+            &mut self,
+            lock_id: u32,
+        ) -> Result<(), LockError> {
+            self.non_reentrant(|contract| {
+                let token_details = contract.releasable_lock(lock_id)?;
+                let unlock_time = token_details.start_time.checked_add(token_details.duration_time).ok_or(LockError::InvalidSchedule)?;
+                if unlock_time > contract.time_now() {
+                    return Err(LockError::NotExpired);
+                }
+
+                let amount = token_details.locked_amount - token_details.released_amount;
+                contract.transfer_released(lock_id, token_details, amount)
+            })
+        }
+
+        /// Sends the vested but not yet released tokens of a lock to its beneficiary.
+        #[ink(message)]
+        pub fn release(
+            &mut self,
+            lock_id: u32,
+        ) -> Result<Balance, LockError> {
+            self.non_reentrant(|contract| {
+                let token_details = contract.releasable_lock(lock_id)?;
+                let amount = Self::vested_amount(&token_details, contract.time_now()).saturating_sub(token_details.released_amount);
+                if amount == 0 {
+                    return Err(LockError::NotExpired);
+                }
+
+                contract.transfer_released(lock_id, token_details, amount)?;
+                Ok(amount)
+            })
+        }
+
+        /// Amount of a lock that has vested but not been released yet.
+        #[ink(message)]
+        pub fn releasable_amount(&self, lock_id: u32) -> Balance {
+            self.token_lock_details
+                .get(lock_id)
+                .filter(|token_details| !token_details.released)
+                .map(|token_details| Self::vested_amount(&token_details, self.time_now()).saturating_sub(token_details.released_amount))
+                .unwrap_or(0)
+        }
+
+        // Records `amount` as released before sending it to the beneficiary.
+        fn transfer_released(
+            &mut self,
+            lock_id: u32,
+            mut token_details: TimeLockDetails,
+            amount: Balance,
+        ) -> Result<(), LockError> {
+            token_details.released_amount = token_details.released_amount.checked_add(amount).ok_or(LockError::InvalidAmount)?;
+            token_details.released = token_details.released_amount == token_details.locked_amount;
+            self.token_lock_details.insert(lock_id, &token_details);
+
+            if token_details.released {
+                // Burn the receipt.
+                let balance = self.receipt_balances.get(token_details.token_owner).unwrap_or(0);
+                self.receipt_balances.insert(token_details.token_owner, &balance.saturating_sub(1));
+                self.receipt_supply -= 1;
+                self.receipt_approvals.remove(lock_id);
+                self.env().emit_event(Transfer { from: Some(token_details.token_owner), to: None, id: Id::U32(lock_id) });
+            }
+
+            match token_details.token_address {
+                Some(token_address) => {
+                    let mut token_contract: TokenContractRef = ink::env::call::FromAccountId::from_account_id(token_address);
+                    token_contract
+                        .call_mut()
+                        .transfer(token_details.beneficiary, amount, Vec::new())
+                        .call_flags(CallFlags::ALLOW_REENTRY)
+                        .invoke()?;
+                }
+                None => self
+                    .env()
+                    .transfer(token_details.beneficiary, amount)
+                    .map_err(|_| LockError::NativeTransferFailed)?,
+            }
+
+            self.env().emit_event(LockReleased { lock_id, beneficiary: token_details.beneficiary, amount });
+            Ok(())
+        }
+
+        // Total amount of a lock unlocked at `now`, including already released tokens.
+        // Extending a vesting lock can drop it below the released amount.
+        fn vested_amount(token_details: &TimeLockDetails, now: Timestamp) -> Balance {
+            let unlock_time = token_details.start_time.saturating_add(token_details.duration_time);
+            if now >= unlock_time {
+                return token_details.locked_amount;
+            }
+            let vesting = match &token_details.schedule {
+                None => return 0,
+                Some(Schedule::Milestones(steps)) => {
+                    return steps
+                        .iter()
+                        .take_while(|(unlock_time, _)| *unlock_time <= now)
+                        .map(|(_, amount)| amount)
+                        .sum();
+                }
+                Some(Schedule::Linear(vesting)) => vesting,
+            };
+            if now < token_details.start_time.saturating_add(vesting.cliff) {
+                return 0;
+            }
+
+            let mut elapsed = now - token_details.start_time;
+            if let Some(slice_period) = vesting.slice_period {
+                elapsed -= elapsed % slice_period;
+            }
+            // Split `locked_amount * elapsed / duration_time` to avoid overflowing.
+            let duration = Balance::from(token_details.duration_time);
+            let elapsed = Balance::from(elapsed);
+            token_details.locked_amount / duration * elapsed
+                + token_details.locked_amount % duration * elapsed / duration
+        }
+
+        #[ink(message)]
+        pub fn get_lock(&self, lock_id: u32) -> Option<LockView> {
+            let token_details = self.token_lock_details.get(lock_id)?;
+            let releasable_amount = self.releasable_amount(lock_id);
+
+            Some(LockView {
+                lock_id,
+                token_address: token_details.token_address,
+                owner: token_details.token_owner,
+                beneficiary: token_details.beneficiary,
+                pending_owner: token_details.pending_owner,
+                locked_amount: token_details.locked_amount,
+                released_amount: token_details.released_amount,
+                releasable_amount,
+                start_time: token_details.start_time,
+                unlock_time: token_details.start_time.saturating_add(token_details.duration_time),
+                schedule: token_details.schedule,
+                released: token_details.released,
+            })
+        }
+
+        /// Locks of a token in creation order, `limit` capped at `MAX_PAGE_SIZE`.
+        #[ink(message)]
+        pub fn locks_by_token(&self, token_address: AccountId, offset: u32, limit: u32) -> Vec<LockView> {
+            let count = self.token_lock_counts.get(token_address).unwrap_or(0);
+            Self::page(offset, limit, count)
+                .filter_map(|position| self.locks_by_token.get((token_address, position)))
+                .filter_map(|lock_id| self.get_lock(lock_id))
+                .collect()
+        }
+
+        /// Locks currently owned by `owner`, `limit` capped at `MAX_PAGE_SIZE`.
+        #[ink(message)]
+        pub fn locks_by_owner(&self, owner: AccountId, offset: u32, limit: u32) -> Vec<LockView> {
+            let count = self.owner_lock_counts.get(owner).unwrap_or(0);
+            Self::page(offset, limit, count)
+                .filter_map(|position| self.locks_by_owner.get((owner, position)))
+                .filter_map(|lock_id| self.get_lock(lock_id))
+                .collect()
+        }
+
+        fn page(offset: u32, limit: u32, count: u32) -> core::ops::Range<u32> {
+            let start = offset.min(count);
+            start..start.saturating_add(limit.min(MAX_PAGE_SIZE)).min(count)
+        }
+
+        // Ids of all locks of a token.
+        fn token_lock_ids(&self, token_address: AccountId) -> impl Iterator<Item = u32> + '_ {
+            (0..self.token_lock_counts.get(token_address).unwrap_or(0))
+                .filter_map(move |position| self.locks_by_token.get((token_address, position)))
+        }
+
+        /// Amount of a token still held in its locks.
+        #[ink(message)]
+        pub fn total_locked(&self, token_address: AccountId) -> Balance {
+            self.token_lock_ids(token_address)
+                .filter_map(|lock_id| self.token_lock_details.get(lock_id))
+                .map(|token_details| token_details.locked_amount - token_details.released_amount)
+                .sum()
+        }
+
+        /// Earliest upcoming time at which locked tokens of `token_address` unlock.
+        #[ink(message)]
+        pub fn next_unlock(&self, token_address: AccountId) -> Option<Timestamp> {
+            let now = self.time_now();
+            self.token_lock_ids(token_address)
+                .filter_map(|lock_id| self.token_lock_details.get(lock_id))
+                .filter(|token_details| !token_details.released)
+                .filter_map(|token_details| Self::next_unlock_time(&token_details, now))
+                .min()
+        }
+
+        // Next time after `now` the vested amount of a lock grows. Continuous vesting
+        // counts as unlocking at its end once the cliff has passed.
+        fn next_unlock_time(token_details: &TimeLockDetails, now: Timestamp) -> Option<Timestamp> {
+            let unlock_time = token_details.start_time.saturating_add(token_details.duration_time);
+            let next = match &token_details.schedule {
+                None => unlock_time,
+                Some(Schedule::Milestones(steps)) => {
+                    return steps.iter().map(|(unlock_time, _)| *unlock_time).find(|unlock_time| *unlock_time > now);
+                }
+                Some(Schedule::Linear(vesting)) => {
+                    let cliff_end = token_details.start_time.saturating_add(vesting.cliff);
+                    match vesting.slice_period {
+                        Some(slice_period) => {
+                            let elapsed = now.saturating_sub(token_details.start_time);
+                            let next_slice = token_details.start_time.saturating_add((elapsed / slice_period + 1) * slice_period);
+                            next_slice.max(cliff_end).min(unlock_time)
+                        }
+                        None if now < cliff_end => cliff_end,
+                        None => unlock_time,
+                    }
+                }
+            };
+            (next > now).then_some(next)
         }
 
         /// Check contract balance
@@ -87,4 +928,636 @@ mod token_lock {
             self.env().block_timestamp()
         }
     }
+    impl PSP34 for TokenLock {
+        /// The lock contract itself.
+        #[ink(message)]
+        fn collection_id(&self) -> Id {
+            Id::Bytes(<_ as AsRef<[u8; 32]>>::as_ref(&self.env().account_id()).to_vec())
+        }
+
+        /// Number of lock receipts held by `owner`.
+        #[ink(message)]
+        fn balance_of(&self, owner: AccountId) -> u32 {
+            self.receipt_balances.get(owner).unwrap_or(0)
+        }
+
+        /// Holder of the receipt of a lock, `None` once the lock is fully released.
+        #[ink(message)]
+        fn owner_of(&self, id: Id) -> Option<AccountId> {
+            self.receipt_owner(&id)
+        }
+
+        #[ink(message)]
+        fn allowance(&self, owner: AccountId, operator: AccountId, id: Option<Id>) -> bool {
+            if self.receipt_operators.contains((owner, operator)) {
+                return true;
+            }
+            match id {
+                Some(Id::U32(lock_id)) => {
+                    self.receipt_owner(&Id::U32(lock_id)) == Some(owner) && self.receipt_approvals.get(lock_id) == Some(operator)
+                }
+                _ => false,
+            }
+        }
+
+        /// Lets `operator` transfer the receipt of a lock, or all receipts of the
+        /// caller if `id` is `None`, or revokes that right.
+        #[ink(message)]
+        fn approve(&mut self, operator: AccountId, id: Option<Id>, approved: bool) -> Result<(), PSP34Error> {
+            let owner = self.env().caller();
+            if operator == owner {
+                return Err(PSP34Error::SelfApprove);
+            }
+
+            match &id {
+                Some(id) => {
+                    let Id::U32(lock_id) = *id else { return Err(PSP34Error::TokenNotExists) };
+                    if self.receipt_owner(id).ok_or(PSP34Error::TokenNotExists)? != owner {
+                        return Err(PSP34Error::NotApproved);
+                    }
+                    if approved {
+                        self.receipt_approvals.insert(lock_id, &operator);
+                    } else {
+                        self.receipt_approvals.remove(lock_id);
+                    }
+                }
+                None if approved => {
+                    self.receipt_operators.insert((owner, operator), &());
+                }
+                None => self.receipt_operators.remove((owner, operator)),
+            }
+            self.env().emit_event(Approval { owner, operator, id, approved });
+            Ok(())
+        }
+
+        /// Transfers the receipt of a lock, and with it the ownership of the lock, to `to`.
+        ///
+        /// The releases of the lock go to `to` from then on as well.
+        #[ink(message)]
+        fn transfer(&mut self, to: AccountId, id: Id, _data: Vec<u8>) -> Result<(), PSP34Error> {
+            let owner = self.receipt_owner(&id).ok_or(PSP34Error::TokenNotExists)?;
+            let caller = self.env().caller();
+            if caller != owner && !self.allowance(owner, caller, Some(id.clone())) {
+                return Err(PSP34Error::NotApproved);
+            }
+
+            let Id::U32(lock_id) = id else { return Err(PSP34Error::TokenNotExists) };
+            let mut token_details = self.token_lock_details.get(lock_id).ok_or(PSP34Error::TokenNotExists)?;
+            token_details.beneficiary = to;
+            self.move_lock(lock_id, token_details, to);
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn total_supply(&self) -> u128 {
+            self.receipt_supply
+        }
+    }
+
+    impl PSP34Metadata for TokenLock {
+        /// Receipt metadata: `token`, `amount` (still locked) and `unlock_time`, SCALE encoded.
+        #[ink(message)]
+        fn get_attribute(&self, id: Id, key: Vec<u8>) -> Option<Vec<u8>> {
+            let Id::U32(lock_id) = id else { return None };
+            let token_details = self.token_lock_details.get(lock_id).filter(|token_details| !token_details.released)?;
+            match key.as_slice() {
+                b"token" => Some(ink::scale::Encode::encode(&token_details.token_address)),
+                b"amount" => Some(ink::scale::Encode::encode(
+                    &(token_details.locked_amount - token_details.released_amount),
+                )),
+                b"unlock_time" => Some(ink::scale::Encode::encode(
+                    &token_details.start_time.saturating_add(token_details.duration_time),
+                )),
+                _ => None,
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use ink::env::test::{
+            callee, default_accounts, get_account_balance, recorded_events, set_account_balance, set_block_timestamp,
+            set_callee, set_caller, set_value_transferred,
+        };
+
+        type E = ink::env::DefaultEnvironment;
+
+        // Cross-contract calls don't work off-chain, so locks are inserted directly.
+        fn add_lock(contract: &mut TokenLock, owner: AccountId) -> u32 {
+            let lock = TimeLockDetails {
+                token_address: Some(AccountId::from([0x01; 32])),
+                token_owner: owner,
+                beneficiary: owner,
+                pending_owner: None,
+                locked_amount: 100,
+                start_time: 0,
+                duration_time: 50,
+                schedule: None,
+                released_amount: 0,
+                released: false,
+            };
+            contract.store_lock(&lock)
+        }
+
+        fn owner_lock_ids(contract: &TokenLock, owner: AccountId) -> Vec<u32> {
+            contract.locks_by_owner(owner, 0, MAX_PAGE_SIZE).iter().map(|lock| lock.lock_id).collect()
+        }
+
+        fn add_vesting_lock(contract: &mut TokenLock, vesting: Vesting) -> u32 {
+            let lock_id = add_lock(contract, default_accounts::<E>().alice);
+            let mut lock = contract.token_lock_details.get(lock_id).unwrap();
+            lock.start_time = 100;
+            lock.duration_time = 1_000;
+            lock.locked_amount = 1_000;
+            lock.schedule = Some(Schedule::Linear(vesting));
+            contract.token_lock_details.insert(lock_id, &lock);
+            lock_id
+        }
+
+        #[ink::test]
+        fn linear_vesting_works() {
+            let mut contract = TokenLock::new();
+            let lock_id = add_vesting_lock(&mut contract, Vesting { cliff: 200, slice_period: None });
+
+            set_block_timestamp::<E>(299);
+            assert_eq!(contract.releasable_amount(lock_id), 0);
+            set_block_timestamp::<E>(300);
+            assert_eq!(contract.releasable_amount(lock_id), 200);
+            set_block_timestamp::<E>(850);
+            assert_eq!(contract.releasable_amount(lock_id), 750);
+            set_block_timestamp::<E>(2_000);
+            assert_eq!(contract.releasable_amount(lock_id), 1_000);
+        }
+
+        #[ink::test]
+        fn sliced_vesting_works() {
+            let mut contract = TokenLock::new();
+            let lock_id = add_vesting_lock(&mut contract, Vesting { cliff: 0, slice_period: Some(250) });
+
+            set_block_timestamp::<E>(349);
+            assert_eq!(contract.releasable_amount(lock_id), 0);
+            set_block_timestamp::<E>(849);
+            assert_eq!(contract.releasable_amount(lock_id), 500);
+
+            let mut lock = contract.token_lock_details.get(lock_id).unwrap();
+            lock.released_amount = 250;
+            contract.token_lock_details.insert(lock_id, &lock);
+            assert_eq!(contract.releasable_amount(lock_id), 250);
+        }
+
+        #[ink::test]
+        fn milestone_schedule_works() {
+            let mut contract = TokenLock::new();
+            let lock_id = add_lock(&mut contract, default_accounts::<E>().alice);
+            let mut lock = contract.token_lock_details.get(lock_id).unwrap();
+            lock.locked_amount = 1_000;
+            lock.duration_time = 300;
+            lock.schedule = Some(Schedule::Milestones(vec![(0, 100), (100, 200), (200, 300), (300, 400)]));
+            contract.token_lock_details.insert(lock_id, &lock);
+
+            set_block_timestamp::<E>(99);
+            assert_eq!(contract.releasable_amount(lock_id), 100);
+            set_block_timestamp::<E>(200);
+            assert_eq!(contract.releasable_amount(lock_id), 600);
+            set_block_timestamp::<E>(300);
+            assert_eq!(contract.releasable_amount(lock_id), 1_000);
+        }
+
+        #[ink::test]
+        fn milestones_must_add_up() {
+            let acc = default_accounts::<E>();
+            let mut contract = TokenLock::new();
+            let milestones = vec![
+                Milestone { unlock_time: 0, share: MilestoneShare::Percentage(10) },
+                Milestone { unlock_time: 100, share: MilestoneShare::Amount(800) },
+            ];
+            assert_eq!(
+                contract.create_milestone_lock(AccountId::from([0x01; 32]), acc.alice, 1_000, milestones),
+                Err(LockError::InvalidAmount)
+            );
+        }
+
+        #[ink::test]
+        fn percentage_milestones_round_into_last() {
+            let milestones: Vec<Milestone> = [(0, 10), (100, 20), (200, 30), (300, 40)]
+                .into_iter()
+                .map(|(unlock_time, percentage)| Milestone { unlock_time, share: MilestoneShare::Percentage(percentage) })
+                .collect();
+            assert_eq!(
+                TokenLock::milestone_steps(999, &milestones),
+                Ok(vec![(0, 99), (100, 199), (200, 299), (300, 402)])
+            );
+        }
+
+        #[ink::test]
+        fn overflows_are_returned_as_errors() {
+            let mut contract = TokenLock::new();
+            let lock_id = add_lock(&mut contract, default_accounts::<E>().alice);
+            assert_eq!(contract.top_up_lock(lock_id, Balance::MAX), Err(LockError::InvalidAmount));
+
+            let milestones = [Milestone { unlock_time: 0, share: MilestoneShare::Percentage(50) }];
+            assert_eq!(TokenLock::milestone_steps(Balance::MAX, &milestones), Err(LockError::InvalidAmount));
+        }
+
+        #[ink::test]
+        fn release_before_cliff_fails() {
+            let mut contract = TokenLock::new();
+            let lock_id = add_vesting_lock(&mut contract, Vesting { cliff: 200, slice_period: None });
+            set_block_timestamp::<E>(250);
+            assert_eq!(contract.release(lock_id), Err(LockError::NotExpired));
+        }
+
+        #[ink::test]
+        fn release_before_expiry_fails() {
+            let acc = default_accounts::<E>();
+            let mut contract = TokenLock::new();
+            let lock_id = add_lock(&mut contract, acc.alice);
+            set_block_timestamp::<E>(49);
+            assert_eq!(contract.release_lock(lock_id), Err(LockError::NotExpired));
+        }
+
+        #[ink::test]
+        fn release_requires_owner() {
+            let acc = default_accounts::<E>();
+            let mut contract = TokenLock::new();
+            let lock_id = add_lock(&mut contract, acc.alice);
+            set_block_timestamp::<E>(50);
+            set_caller::<E>(acc.bob);
+            assert_eq!(contract.release_lock(lock_id), Err(LockError::Unauthorized));
+            assert_eq!(contract.release_lock(7), Err(LockError::NotFound));
+        }
+
+        #[ink::test]
+        fn release_only_once() {
+            let acc = default_accounts::<E>();
+            let mut contract = TokenLock::new();
+            let lock_id = add_lock(&mut contract, acc.alice);
+            let mut lock = contract.token_lock_details.get(lock_id).unwrap();
+            lock.released = true;
+            contract.token_lock_details.insert(lock_id, &lock);
+            set_block_timestamp::<E>(50);
+            assert_eq!(contract.release_lock(lock_id), Err(LockError::AlreadyReleased));
+        }
+
+        #[ink::test]
+        fn native_lock_works() {
+            let acc = default_accounts::<E>();
+            set_callee::<E>(AccountId::from([0xff; 32]));
+            let mut contract = TokenLock::new();
+
+            set_value_transferred::<E>(500);
+            let lock_id = contract.create_native_lock(acc.bob, 50).unwrap();
+            set_account_balance::<E>(callee::<E>(), 1_000_000);
+
+            set_block_timestamp::<E>(50);
+            set_caller::<E>(acc.bob);
+            let before = get_account_balance::<E>(acc.bob).unwrap();
+            assert_eq!(contract.release_lock(lock_id), Ok(()));
+            assert_eq!(get_account_balance::<E>(acc.bob).unwrap(), before + 500);
+            assert!(contract.token_lock_details.get(lock_id).unwrap().released);
+            assert_eq!(contract.owner_of(Id::U32(lock_id)), None);
+            assert_eq!(contract.balance_of(acc.bob), 0);
+            assert_eq!(contract.total_supply(), 0);
+        }
+
+        #[ink::test]
+        fn native_vesting_lock_works() {
+            let acc = default_accounts::<E>();
+            set_callee::<E>(AccountId::from([0xff; 32]));
+            let mut contract = TokenLock::new();
+
+            set_value_transferred::<E>(1_000);
+            let lock_id = contract.create_native_vesting_lock(acc.bob, 0, 0, 100, None).unwrap();
+            set_account_balance::<E>(callee::<E>(), 1_000_000);
+
+            set_block_timestamp::<E>(40);
+            set_caller::<E>(acc.bob);
+            assert_eq!(contract.release(lock_id), Ok(400));
+            assert_eq!(contract.releasable_amount(lock_id), 0);
+        }
+
+        #[ink::test]
+        fn extend_lock_works() {
+            let acc = default_accounts::<E>();
+            let mut contract = TokenLock::new();
+            let lock_id = add_lock(&mut contract, acc.alice);
+            assert_eq!(contract.extend_lock(lock_id, 80), Ok(()));
+            set_block_timestamp::<E>(79);
+            assert_eq!(contract.releasable_amount(lock_id), 0);
+            set_block_timestamp::<E>(80);
+            assert_eq!(contract.releasable_amount(lock_id), 100);
+            // Receipt mint, creation and extension.
+            assert_eq!(recorded_events().count(), 3);
+        }
+
+        #[ink::test]
+        fn extend_lock_cannot_shorten() {
+            let acc = default_accounts::<E>();
+            let mut contract = TokenLock::new();
+            let lock_id = add_lock(&mut contract, acc.alice);
+            assert_eq!(contract.extend_lock(lock_id, 49), Err(LockError::InvalidSchedule));
+        }
+
+        #[ink::test]
+        fn split_lock_works() {
+            let acc = default_accounts::<E>();
+            let mut contract = TokenLock::new();
+            let lock_id = add_lock(&mut contract, acc.alice);
+            let new_lock_id = contract.split_lock(lock_id, 30, acc.bob).unwrap();
+
+            let new_lock = contract.token_lock_details.get(new_lock_id).unwrap();
+            assert_eq!(new_lock.token_owner, acc.alice);
+            assert_eq!(new_lock.beneficiary, acc.bob);
+            assert_eq!(new_lock.duration_time, 50);
+            assert_eq!(owner_lock_ids(&contract, acc.alice), vec![lock_id, new_lock_id]);
+
+            set_block_timestamp::<E>(50);
+            assert_eq!(contract.releasable_amount(lock_id), 70);
+            assert_eq!(contract.releasable_amount(new_lock_id), 30);
+        }
+
+        #[ink::test]
+        fn split_keeps_releasable_amount() {
+            let acc = default_accounts::<E>();
+            let mut contract = TokenLock::new();
+            let lock_id = add_vesting_lock(&mut contract, Vesting { cliff: 0, slice_period: None });
+            let mut lock = contract.token_lock_details.get(lock_id).unwrap();
+            lock.released_amount = 500;
+            contract.token_lock_details.insert(lock_id, &lock);
+
+            // Half vested and all of it released.
+            set_block_timestamp::<E>(600);
+            assert_eq!(contract.releasable_amount(lock_id), 0);
+            let new_lock_id = contract.split_lock(lock_id, 400, acc.bob).unwrap();
+            assert_eq!(contract.releasable_amount(lock_id) + contract.releasable_amount(new_lock_id), 0);
+
+            set_block_timestamp::<E>(850);
+            assert_eq!(contract.releasable_amount(lock_id) + contract.releasable_amount(new_lock_id), 250);
+        }
+
+        #[ink::test]
+        fn lock_ownership_transfer_works() {
+            let acc = default_accounts::<E>();
+            let mut contract = TokenLock::new();
+            let lock_id = add_lock(&mut contract, acc.alice);
+
+            assert_eq!(contract.transfer_lock_ownership(lock_id, acc.bob), Ok(()));
+            assert_eq!(contract.token_lock_details.get(lock_id).unwrap().token_owner, acc.alice);
+
+            set_caller::<E>(acc.bob);
+            assert_eq!(contract.accept_lock_ownership(lock_id), Ok(()));
+            let lock = contract.token_lock_details.get(lock_id).unwrap();
+            assert_eq!(lock.token_owner, acc.bob);
+            assert_eq!(lock.beneficiary, acc.alice);
+            assert_eq!(lock.pending_owner, None);
+            assert_eq!(owner_lock_ids(&contract, acc.alice), Vec::<u32>::new());
+            assert_eq!(owner_lock_ids(&contract, acc.bob), vec![lock_id]);
+        }
+
+        #[ink::test]
+        fn accept_lock_ownership_requires_proposal() {
+            let acc = default_accounts::<E>();
+            let mut contract = TokenLock::new();
+            let lock_id = add_lock(&mut contract, acc.alice);
+            assert_eq!(contract.transfer_lock_ownership(lock_id, acc.bob), Ok(()));
+            set_caller::<E>(acc.charlie);
+            assert_eq!(contract.accept_lock_ownership(lock_id), Err(LockError::Unauthorized));
+        }
+
+        #[ink::test]
+        fn receipt_transfer_moves_lock() {
+            let acc = default_accounts::<E>();
+            let mut contract = TokenLock::new();
+            let lock_id = add_lock(&mut contract, acc.alice);
+            assert_eq!(contract.owner_of(Id::U32(lock_id)), Some(acc.alice));
+            assert_eq!(contract.balance_of(acc.alice), 1);
+            assert_eq!(contract.total_supply(), 1);
+
+            set_caller::<E>(acc.bob);
+            assert_eq!(contract.transfer(acc.bob, Id::U32(lock_id), Vec::new()), Err(PSP34Error::NotApproved));
+
+            set_caller::<E>(acc.alice);
+            assert_eq!(contract.approve(acc.bob, Some(Id::U32(lock_id)), true), Ok(()));
+            assert!(contract.allowance(acc.alice, acc.bob, Some(Id::U32(lock_id))));
+
+            set_caller::<E>(acc.bob);
+            assert_eq!(contract.transfer(acc.charlie, Id::U32(lock_id), Vec::new()), Ok(()));
+            assert_eq!(contract.owner_of(Id::U32(lock_id)), Some(acc.charlie));
+            assert_eq!(contract.balance_of(acc.alice), 0);
+            assert_eq!(contract.balance_of(acc.charlie), 1);
+            assert!(!contract.allowance(acc.charlie, acc.bob, Some(Id::U32(lock_id))));
+            let lock = contract.token_lock_details.get(lock_id).unwrap();
+            assert_eq!(lock.token_owner, acc.charlie);
+            assert_eq!(lock.beneficiary, acc.charlie);
+            assert_eq!(owner_lock_ids(&contract, acc.charlie), vec![lock_id]);
+        }
+
+        #[ink::test]
+        fn operator_can_transfer_all_receipts() {
+            let acc = default_accounts::<E>();
+            let mut contract = TokenLock::new();
+            let first = add_lock(&mut contract, acc.alice);
+            let second = add_lock(&mut contract, acc.alice);
+
+            assert_eq!(contract.approve(acc.bob, None, true), Ok(()));
+            assert!(contract.allowance(acc.alice, acc.bob, None));
+
+            set_caller::<E>(acc.bob);
+            assert_eq!(contract.transfer(acc.charlie, Id::U32(first), Vec::new()), Ok(()));
+            assert_eq!(contract.transfer(acc.charlie, Id::U32(second), Vec::new()), Ok(()));
+            assert_eq!(contract.balance_of(acc.charlie), 2);
+            assert_eq!(contract.owner_of(Id::U64(first.into())), None);
+        }
+
+        #[ink::test]
+        fn receipt_transfer_redirects_releases() {
+            let acc = default_accounts::<E>();
+            let mut contract = TokenLock::new();
+            let lock_id = add_lock(&mut contract, acc.alice);
+            assert_eq!(contract.set_beneficiary(lock_id, acc.django), Ok(()));
+
+            assert_eq!(contract.transfer(acc.bob, Id::U32(lock_id), Vec::new()), Ok(()));
+            assert_eq!(contract.get_lock(lock_id).unwrap().beneficiary, acc.bob);
+
+            // Neither the seller nor the previous beneficiary can release anymore.
+            set_block_timestamp::<E>(50);
+            assert_eq!(contract.release_lock(lock_id), Err(LockError::Unauthorized));
+            set_caller::<E>(acc.django);
+            assert_eq!(contract.release_lock(lock_id), Err(LockError::Unauthorized));
+        }
+
+        #[ink::test]
+        fn receipt_attributes_work() {
+            let acc = default_accounts::<E>();
+            let mut contract = TokenLock::new();
+            let lock_id = add_lock(&mut contract, acc.alice);
+
+            let amount = contract.get_attribute(Id::U32(lock_id), b"amount".to_vec()).unwrap();
+            assert_eq!(<Balance as ink::scale::Decode>::decode(&mut &amount[..]), Ok(100));
+            let unlock_time = contract.get_attribute(Id::U32(lock_id), b"unlock_time".to_vec()).unwrap();
+            assert_eq!(<Timestamp as ink::scale::Decode>::decode(&mut &unlock_time[..]), Ok(50));
+            assert_eq!(contract.get_attribute(Id::U32(lock_id), b"name".to_vec()), None);
+        }
+
+        #[ink::test]
+        fn owner_index_keeps_remaining_locks() {
+            let acc = default_accounts::<E>();
+            let mut contract = TokenLock::new();
+            let first = add_lock(&mut contract, acc.alice);
+            let second = add_lock(&mut contract, acc.alice);
+            let third = add_lock(&mut contract, acc.alice);
+
+            assert_eq!(contract.transfer(acc.bob, Id::U32(first), Vec::new()), Ok(()));
+            assert_eq!(owner_lock_ids(&contract, acc.alice), vec![third, second]);
+            assert_eq!(contract.transfer(acc.bob, Id::U32(second), Vec::new()), Ok(()));
+            assert_eq!(owner_lock_ids(&contract, acc.alice), vec![third]);
+            assert_eq!(owner_lock_ids(&contract, acc.bob), vec![first, second]);
+        }
+
+        #[ink::test]
+        fn empty_locks_are_rejected() {
+            let acc = default_accounts::<E>();
+            let mut contract = TokenLock::new();
+            let token = AccountId::from([0x01; 32]);
+            assert_eq!(contract.create_lock(token, acc.alice, 0, 50), Err(LockError::InvalidAmount));
+            assert_eq!(contract.create_vesting_lock(token, acc.alice, 0, 0, 0, 50, None), Err(LockError::InvalidAmount));
+        }
+
+        #[ink::test]
+        fn lock_queries_work() {
+            let acc = default_accounts::<E>();
+            let mut contract = TokenLock::new();
+            let first = add_lock(&mut contract, acc.alice);
+            let second = add_vesting_lock(&mut contract, Vesting { cliff: 0, slice_period: Some(250) });
+            let token = AccountId::from([0x01; 32]);
+
+            let view = contract.get_lock(first).unwrap();
+            assert_eq!(view.owner, acc.alice);
+            assert_eq!(view.locked_amount, 100);
+            assert_eq!(view.unlock_time, 50);
+            assert_eq!(contract.get_lock(3), None);
+
+            assert_eq!(contract.locks_by_token(token, 0, 10).len(), 2);
+            assert_eq!(contract.locks_by_owner(acc.alice, 1, 10)[0].lock_id, second);
+            assert_eq!(contract.total_locked(token), 1_100);
+
+            assert_eq!(contract.next_unlock(token), Some(50));
+            set_block_timestamp::<E>(50);
+            assert_eq!(contract.next_unlock(token), Some(350));
+            set_block_timestamp::<E>(400);
+            assert_eq!(contract.next_unlock(token), Some(600));
+        }
+
+        #[ink::test]
+        fn split_lock_requires_owner() {
+            let acc = default_accounts::<E>();
+            let mut contract = TokenLock::new();
+            let lock_id = add_lock(&mut contract, acc.alice);
+            set_caller::<E>(acc.bob);
+            assert_eq!(contract.split_lock(lock_id, 30, acc.bob), Err(LockError::Unauthorized));
+        }
+    }
+
+    #[cfg(all(test, feature = "e2e-tests"))]
+    mod e2e_tests {
+        use super::*;
+        use ink_e2e::ContractsBackend;
+        use malicious_token::{MaliciousToken, MaliciousTokenRef};
+        type E2EResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+        /// A token re-entering `release_lock` from inside `transfer_from` must not be
+        /// able to release a lock in the middle of another lock operation.
+        ///
+        /// The token owns an expired lock, so the reentrant release is authorized and
+        /// would go through if the guard didn't reject it.
+        #[ink_e2e::test]
+        async fn reentrant_token_cannot_release_lock<Client: E2EBackend>(
+            mut client: Client,
+        ) -> E2EResult<()> {
+            let mut token_constructor = MaliciousTokenRef::new(1_000);
+            let token = client
+                .instantiate("malicious-token", &ink_e2e::alice(), &mut token_constructor)
+                .submit()
+                .await
+                .expect("malicious-token instantiate failed");
+            let mut token_call = token.call_builder::<MaliciousToken>();
+
+            let mut lock_constructor = TokenLockRef::new();
+            let lock = client
+                .instantiate("token-lock", &ink_e2e::alice(), &mut lock_constructor)
+                .submit()
+                .await
+                .expect("token-lock instantiate failed");
+            let mut lock_call = lock.call_builder::<TokenLock>();
+
+            let alice = ink_e2e::account_id(ink_e2e::AccountKeyring::Alice);
+            const DURATION: Timestamp = 1_000_000;
+
+            let approve = token_call.approve(lock.account_id, 200);
+            client
+                .call(&ink_e2e::alice(), &approve)
+                .submit()
+                .await
+                .expect("Calling `approve` failed");
+
+            // Expires right away and can be released by the token.
+            let create = lock_call.create_lock(token.account_id, token.account_id, 100, 0);
+            let lock_id = client
+                .call(&ink_e2e::alice(), &create)
+                .submit()
+                .await
+                .expect("Calling `create_lock` failed")
+                .return_value()
+                .expect("Creating the expired lock failed");
+
+            // The next transfer of the token calls `release_lock` first.
+            let arm = token_call.set_reentry(
+                lock.account_id,
+                ink::selector_bytes!("release_lock"),
+                ink::scale::Encode::encode(&lock_id),
+            );
+            client
+                .call(&ink_e2e::alice(), &arm)
+                .submit()
+                .await
+                .expect("Calling `set_reentry` failed");
+
+            let create = lock_call.create_lock(token.account_id, alice, 100, DURATION);
+            client
+                .call(&ink_e2e::alice(), &create)
+                .submit()
+                .await
+                .expect("Calling `create_lock` failed")
+                .return_value()
+                .expect("Creating the second lock failed");
+
+            let get_lock = lock_call.get_lock(lock_id);
+            let expired_lock = client
+                .call(&ink_e2e::alice(), &get_lock)
+                .dry_run()
+                .await?
+                .return_value()
+                .expect("Lock not found");
+            assert!(!expired_lock.released);
+
+            let balance_of = token_call.balance_of(lock.account_id);
+            let locked = client
+                .call(&ink_e2e::alice(), &balance_of)
+                .dry_run()
+                .await?
+                .return_value();
+            assert_eq!(locked, 200);
+
+            let balance_of = token_call.balance_of(token.account_id);
+            let released = client
+                .call(&ink_e2e::alice(), &balance_of)
+                .dry_run()
+                .await?
+                .return_value();
+            assert_eq!(released, 0);
+
+            Ok(())
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,85 @@
+use ink::{
+    prelude::{string::String, vec::Vec},
+    primitives::AccountId,
+};
+
+/// Identifier of a PSP34 token. Lock receipts are `Id::U32(lock_id)`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[ink::scale_derive(Encode, Decode, TypeInfo)]
+#[cfg_attr(
+    feature = "std",
+    derive(ink::storage::traits::StorageLayout)
+)]
+pub enum Id {
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    U128(u128),
+    Bytes(Vec<u8>),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+#[ink::scale_derive(Encode, Decode, TypeInfo)]
+pub enum PSP34Error {
+    /// Custom error type for implementation-based errors.
+    Custom(String),
+    /// Returned when the owner approves themselves as an operator.
+    SelfApprove,
+    /// Returned when the caller is not allowed to perform the operation.
+    NotApproved,
+    /// Returned when minting an id that already exists.
+    TokenExists,
+    /// Returned when the id does not exist.
+    TokenNotExists,
+    /// Returned if a safe transfer check failed.
+    SafeTransferCheckFailed(String),
+}
+
+#[ink::trait_definition]
+pub trait PSP34 {
+    /// Returns the collection `Id` of the NFT token.
+    #[ink(message)]
+    fn collection_id(&self) -> Id;
+
+    /// Returns the number of tokens owned by `owner`.
+    #[ink(message)]
+    fn balance_of(&self, owner: AccountId) -> u32;
+
+    /// Returns the owner of the token `id`, if it exists.
+    #[ink(message)]
+    fn owner_of(&self, id: Id) -> Option<AccountId>;
+
+    /// Returns `true` if `operator` may transfer the token `id` of `owner`,
+    /// or all tokens of `owner` if `id` is `None`.
+    #[ink(message)]
+    fn allowance(&self, owner: AccountId, operator: AccountId, id: Option<Id>) -> bool;
+
+    /// Approves or disapproves `operator` to transfer the token `id` of the caller,
+    /// or all their tokens if `id` is `None`.
+    ///
+    /// # Events
+    ///
+    /// On success an `Approval` event is emitted.
+    #[ink(message)]
+    fn approve(&mut self, operator: AccountId, id: Option<Id>, approved: bool) -> Result<(), PSP34Error>;
+
+    /// Transfers the token `id` to `to`, with additional `data` in unspecified format.
+    ///
+    /// # Events
+    ///
+    /// On success a `Transfer` event is emitted.
+    #[ink(message)]
+    fn transfer(&mut self, to: AccountId, id: Id, data: Vec<u8>) -> Result<(), PSP34Error>;
+
+    /// Returns the number of tokens in existence.
+    #[ink(message)]
+    fn total_supply(&self) -> u128;
+}
+
+#[ink::trait_definition]
+pub trait PSP34Metadata {
+    /// Returns the attribute of the token `id` stored under `key`, if any.
+    #[ink(message)]
+    fn get_attribute(&self, id: Id, key: Vec<u8>) -> Option<Vec<u8>>;
+}